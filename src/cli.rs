@@ -0,0 +1,238 @@
+use structopt::StructOpt;
+
+use crate::logger::LogFormat;
+use crate::parser::NozomiSource;
+
+/// # Madome Synchronizer
+/// Synchronize galleries of hitomi.la to Madome
+#[derive(Debug, StructOpt)]
+#[structopt(name = "madome-synchronizer")]
+pub struct Opt {
+    /// Path of config file [default: ./synchronizer.toml]
+    #[structopt(long, env = "SYNCHRONIZER_CONFIG")]
+    pub config: Option<String>,
+
+    #[structopt(long)]
+    pub madome_url: Option<String>,
+
+    #[structopt(long)]
+    pub file_repository_url: Option<String>,
+
+    #[structopt(long)]
+    pub token_path: Option<String>,
+
+    #[structopt(long)]
+    pub fail_store_path: Option<String>,
+
+    /// Path of high-water marks of nozomi sources
+    #[structopt(long)]
+    pub cursor_path: Option<String>,
+
+    /// Directory of uploaded pages of galleries, retries upload the missing pages only
+    #[structopt(long)]
+    pub progress_dir: Option<String>,
+
+    /// Index of uploaded images by hash, galleries sharing images reference them
+    #[structopt(long)]
+    pub hash_index_path: Option<String>,
+
+    /// Journal of stages of incomplete galleries, resumed on startup
+    #[structopt(long)]
+    pub journal_path: Option<String>,
+
+    /// Directory of reports of the last runs of galleries
+    #[structopt(long)]
+    pub report_dir: Option<String>,
+
+    /// Address of prometheus metrics endpoint such as `0.0.0.0:9090`, disabled by default
+    #[structopt(long)]
+    pub metrics_addr: Option<String>,
+
+    /// `text` or `json` [default: text]
+    #[structopt(long)]
+    pub log_format: Option<LogFormat>,
+
+    /// Size of global thread pool
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    pub threads: Option<usize>,
+
+    /// Languages of hitomi nozomi index, can be repeated
+    #[structopt(long = "language", number_of_values = 1)]
+    pub languages: Vec<String>,
+
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Synchronize the galleries newer than the last run, and repeat it after `--latency` seconds
+    SyncLatest {
+        #[structopt(flatten)]
+        paging: Paging,
+
+        #[structopt(flatten)]
+        sources: Sources,
+
+        /// Time until next synchronize (secs) [default: 3600]
+        #[structopt(long)]
+        latency: Option<u64>,
+    },
+
+    /// Synchronize the specified galleries
+    SyncId {
+        /// Gallery IDs of hitomi
+        #[structopt(required = true, min_values = 1)]
+        ids: Vec<u32>,
+    },
+
+    /// Retry synchronize the failed galleries in fail_store
+    RetryFail,
+
+    /// Synchronize all pages of hitomi until the end, and exit
+    Backfill {
+        #[structopt(flatten)]
+        paging: Paging,
+
+        #[structopt(flatten)]
+        sources: Sources,
+    },
+
+    /// Print the state of fail_store and token
+    Status,
+
+    /// Print the report of the last runs of a gallery
+    Report {
+        /// Gallery ID of hitomi
+        id: u32,
+    },
+
+    /// Inspect the config
+    Config(ConfigCommand),
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ConfigCommand {
+    /// Validate the config, and print the resolved config
+    Validate,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Paging {
+    /// Initial page of hitomi
+    #[structopt(long, env = "PAGE", default_value = "1", parse(try_from_str = parse_positive))]
+    pub page: usize,
+
+    /// Per page of hitomi [default: 25]
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    pub per_page: Option<usize>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Sources {
+    /// Nozomi sources instead of the index of languages, can be repeated.
+    /// `tag:<tag>`, `artist:<artist>`, `series:<series>` or `group:<group>`
+    /// e.g. `--source "artist:airandou" --source "tag:female:sole female"` or `--source "tag:sole female ♀"`
+    #[structopt(long = "source", number_of_values = 1, parse(try_from_str = parse_source))]
+    pub specs: Vec<String>,
+}
+
+fn parse_source(x: &str) -> Result<String, String> {
+    NozomiSource::parse(x, "all")
+        .map(|_| x.to_string())
+        .map_err(|err| err.to_string())
+}
+
+fn parse_positive(x: &str) -> Result<usize, String> {
+    match x.parse::<usize>() {
+        Ok(0) => Err("must be greater than 0".to_string()),
+        Ok(x) => Ok(x),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use structopt::StructOpt;
+
+    use super::{Command, Opt};
+
+    #[test]
+    fn parse_sync_id() -> anyhow::Result<()> {
+        let opt = Opt::from_iter_safe(&["madome-synchronizer", "sync-id", "1724122", "1399900"])?;
+
+        match opt.command {
+            Command::SyncId { ids } => assert_eq!(vec![1724122, 1399900], ids),
+            command => panic!("unexpected command {:?}", command),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sync_id_requires_ids() {
+        let r = Opt::from_iter_safe(&["madome-synchronizer", "sync-id"]);
+
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn reject_zero_per_page() {
+        let r = Opt::from_iter_safe(&["madome-synchronizer", "backfill", "--per-page", "0"]);
+
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn parse_global_options() -> anyhow::Result<()> {
+        let opt = Opt::from_iter_safe(&[
+            "madome-synchronizer",
+            "--config",
+            "./staging.toml",
+            "--language",
+            "korean",
+            "--language",
+            "english",
+            "config",
+            "validate",
+        ])?;
+
+        assert_eq!(Some("./staging.toml".to_string()), opt.config);
+        assert_eq!(vec!["korean", "english"], opt.languages);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_sources() -> anyhow::Result<()> {
+        let opt = Opt::from_iter_safe(&[
+            "madome-synchronizer",
+            "backfill",
+            "--source",
+            "artist:airandou",
+            "--source",
+            "series:sword art online",
+        ])?;
+
+        match opt.command {
+            Command::Backfill { sources, .. } => assert_eq!(
+                vec!["artist:airandou", "series:sword art online"],
+                sources.specs
+            ),
+            command => panic!("unexpected command {:?}", command),
+        }
+
+        assert!(
+            Opt::from_iter_safe(&["madome-synchronizer", "backfill", "--source", "lum"]).is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unknown_command() {
+        let r = Opt::from_iter_safe(&["madome-synchronizer", "sync-everything"]);
+
+        assert!(r.is_err());
+    }
+}
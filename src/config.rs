@@ -0,0 +1,930 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::{Command, Opt};
+use crate::error::ErrorKind;
+use crate::logger::LogFormat;
+use crate::parser::ImageFormat;
+use crate::stage::Stage;
+use crate::thumbnail::ThumbnailSize;
+use crate::transcode::{TranscodeFormat, Transcoder};
+use crate::upstream::UpstreamEndpoints;
+
+pub const DEFAULT_CONFIG_PATH: &str = "./synchronizer.toml";
+
+/// Languages of hitomi nozomi index, `all` is the index of every language
+pub const LANGUAGES: [&str; 38] = [
+    "all",
+    "indonesian",
+    "catalan",
+    "cebuano",
+    "czech",
+    "danish",
+    "german",
+    "estonian",
+    "english",
+    "spanish",
+    "esperanto",
+    "french",
+    "italian",
+    "latin",
+    "hungarian",
+    "dutch",
+    "norwegian",
+    "polish",
+    "portuguese",
+    "romanian",
+    "albanian",
+    "slovak",
+    "finnish",
+    "swedish",
+    "tagalog",
+    "vietnamese",
+    "turkish",
+    "greek",
+    "mongolian",
+    "russian",
+    "ukrainian",
+    "hebrew",
+    "arabic",
+    "persian",
+    "thai",
+    "korean",
+    "chinese",
+    "japanese",
+];
+
+/// # Config
+/// Resolved by `file < env < cli`
+///
+/// ```toml
+/// madome_url = "https://api.madome.app"
+/// file_repository_url = "https://file.madome.app"
+/// token_path = "./.token"
+/// fail_store_path = "./fail_store.txt"
+/// cursor_path = "./cursor.json"
+/// progress_dir = "./progress"
+/// hash_index_path = "./hash_index.txt"
+/// journal_path = "./journal.jsonl"
+/// report_dir = "./reports"
+/// metrics_addr = "0.0.0.0:9090"
+/// log_format = "json"
+/// startup_delay = 3
+/// languages = ["korean"]
+///
+/// [sync]
+/// per_page = 25
+/// latency = 3600
+///
+/// [concurrency]
+/// threads = 25
+/// images = 25
+///
+/// [images]
+/// formats = ["avif", "webp", "original"]
+/// thumbnail_sizes = ["small", "medium", "large"]
+///
+/// [transcode]
+/// format = "jpeg"
+/// quality = 85
+/// max_edge = 2560
+///
+/// [retry.add_images]
+/// max_attempts = 3
+/// backoff = 1000
+/// multiplier = 2.0
+/// max_backoff = 30000
+/// jitter = 0.5
+/// retryable = ["upstream_unavailable", "network", "upload_failed"]
+///
+/// [upstream]
+/// ltn = "https://ltn.hitomi.la"
+/// hitomi = "https://hitomi.la"
+/// thumbnail = "https://tn.hitomi.la"
+/// image = "https://{subdomain}.hitomi.la"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub madome_url: String,
+    pub file_repository_url: String,
+    pub token_path: String,
+    pub fail_store_path: String,
+    /// High-water marks of nozomi sources
+    pub cursor_path: String,
+    /// Uploaded pages of galleries not synchronized yet
+    pub progress_dir: String,
+    /// Uploaded images by hash of hitomi, shared by galleries
+    pub hash_index_path: String,
+    /// Stage transitions of incomplete galleries, resumed on startup
+    pub journal_path: String,
+    /// Last runs of galleries, printed by `report <id>`
+    pub report_dir: String,
+    /// Serves prometheus metrics on `http://{metrics_addr}/metrics` if set
+    pub metrics_addr: Option<String>,
+    /// `text` of env_logger or JSON lines, both are filtered by `RUST_LOG`
+    pub log_format: LogFormat,
+    /// secs
+    pub startup_delay: u64,
+    /// Languages of hitomi nozomi index, or `["all"]`
+    pub languages: Vec<String>,
+
+    pub sync: SyncConfig,
+    pub concurrency: ConcurrencyConfig,
+    pub images: ImagesConfig,
+    pub transcode: TranscodeConfig,
+    pub retry: RetryConfig,
+    pub upstream: UpstreamEndpoints,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            madome_url: "https://api.madome.app".to_string(),
+            file_repository_url: "https://file.madome.app".to_string(),
+            token_path: "./.token".to_string(),
+            fail_store_path: "./fail_store.txt".to_string(),
+            cursor_path: "./cursor.json".to_string(),
+            progress_dir: "./progress".to_string(),
+            hash_index_path: "./hash_index.txt".to_string(),
+            journal_path: "./journal.jsonl".to_string(),
+            report_dir: "./reports".to_string(),
+            metrics_addr: None,
+            log_format: LogFormat::default(),
+            startup_delay: 3,
+            languages: vec!["korean".to_string()],
+
+            sync: SyncConfig::default(),
+            concurrency: ConcurrencyConfig::default(),
+            images: ImagesConfig::default(),
+            transcode: TranscodeConfig::default(),
+            retry: RetryConfig::default(),
+            upstream: UpstreamEndpoints::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncConfig {
+    /// Per page of hitomi
+    pub per_page: usize,
+    /// Time until next synchronize (secs)
+    pub latency: u64,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            per_page: 25,
+            latency: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConcurrencyConfig {
+    /// Size of global thread pool, galleries are synchronized on it
+    pub threads: usize,
+    /// Size of thread pool for uploading images of a gallery
+    pub images: usize,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            threads: 25,
+            images: 25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagesConfig {
+    /// Preference of formats, falls back to the next format if not found,
+    /// and to `original` at last
+    pub formats: Vec<ImageFormat>,
+    /// Thumbnails generated from the first page, in addition to `thumbnail.jpg`
+    pub thumbnail_sizes: Vec<ThumbnailSize>,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            formats: vec![ImageFormat::Original],
+            thumbnail_sizes: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TranscodeConfig {
+    /// Re-encodes images before upload if set, images are uploaded as downloaded by default
+    pub format: Option<TranscodeFormat>,
+    /// 1 ~ 100
+    pub quality: u8,
+    /// Longest edge of transcoded images
+    pub max_edge: Option<u32>,
+}
+
+impl Default for TranscodeConfig {
+    fn default() -> Self {
+        Self {
+            format: None,
+            quality: 85,
+            max_edge: None,
+        }
+    }
+}
+
+impl TranscodeConfig {
+    pub fn transcoder(&self) -> Option<Transcoder> {
+        self.format.map(|format| {
+            Transcoder::new(format)
+                .with_quality(self.quality)
+                .with_max_edge(self.max_edge)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    pub parse_book: RetryPolicy,
+    pub parse_images: RetryPolicy,
+    pub add_thumbnail: RetryPolicy,
+    pub add_images: RetryPolicy,
+    pub add_image_list: RetryPolicy,
+    pub add_book: RetryPolicy,
+}
+
+impl RetryConfig {
+    pub fn get(&self, stage: &Stage) -> &RetryPolicy {
+        match stage {
+            Stage::ParseBook => &self.parse_book,
+            Stage::ParseImages => &self.parse_images,
+            Stage::AddThumbnail => &self.add_thumbnail,
+            Stage::AddImages => &self.add_images,
+            Stage::AddImageList => &self.add_image_list,
+            Stage::AddBook => &self.add_book,
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &RetryPolicy> {
+        vec![
+            &self.parse_book,
+            &self.parse_images,
+            &self.add_thumbnail,
+            &self.add_images,
+            &self.add_image_list,
+            &self.add_book,
+        ]
+        .into_iter()
+    }
+}
+
+/// # Retry Policy
+/// Exponential backoff of a stage, `backoff * multiplier ^ (attempt - 1)` capped by `max_backoff`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Includes the first attempt
+    pub max_attempts: usize,
+    /// Time before the first retry (millis)
+    pub backoff: u64,
+    pub multiplier: f64,
+    /// millis
+    pub max_backoff: u64,
+    /// 0.0 ~ 1.0, backoff is shortened by a random ratio up to `jitter`
+    pub jitter: f64,
+    /// Error kinds to retry, [`ErrorKind::is_retryable`] if not set
+    pub retryable: Option<Vec<ErrorKind>>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: 1000,
+            multiplier: 2.0,
+            max_backoff: 30_000,
+            jitter: 0.5,
+            retryable: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn is_retryable(&self, kind: ErrorKind) -> bool {
+        match self.retryable {
+            Some(ref retryable) => retryable.contains(&kind),
+            None => kind.is_retryable(),
+        }
+    }
+
+    /// Backoff after the failed `attempt`, `random` is in `0.0..1.0`
+    pub fn delay(&self, attempt: usize, random: f64) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as usize) as i32;
+        let backoff =
+            (self.backoff as f64 * self.multiplier.powi(exponent)).min(self.max_backoff as f64);
+        let jitter = backoff * self.jitter * random;
+
+        Duration::from_millis((backoff - jitter) as u64)
+    }
+}
+
+impl Config {
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path.as_ref()).map_err(|err| {
+            anyhow::Error::msg(format!(
+                "Can't read config file {}: {}",
+                path.as_ref().display(),
+                err
+            ))
+        })?;
+
+        let config = toml::from_str(&text)?;
+
+        Ok(config)
+    }
+
+    /// Loads config file, and overrides it by environment variables and command line arguments
+    pub fn load(opt: &Opt) -> anyhow::Result<Self> {
+        let mut config = match opt.config {
+            Some(ref path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(DEFAULT_CONFIG_PATH)?
+            }
+            None => Self::default(),
+        };
+
+        config.apply_env(|key| env::var(key).ok())?;
+        config.apply_opt(opt);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn apply_env<F>(&mut self, var: F) -> anyhow::Result<()>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(x) = var("MADOME_URL") {
+            self.madome_url = x;
+        }
+        if let Some(x) = var("FILE_REPOSITORY_URL") {
+            self.file_repository_url = x;
+        }
+        if let Some(x) = var("TOKEN_PATH") {
+            self.token_path = x;
+        }
+        if let Some(x) = var("FAIL_STORE_PATH") {
+            self.fail_store_path = x;
+        }
+        if let Some(x) = var("CURSOR_PATH") {
+            self.cursor_path = x;
+        }
+        if let Some(x) = var("PROGRESS_DIR") {
+            self.progress_dir = x;
+        }
+        if let Some(x) = var("HASH_INDEX_PATH") {
+            self.hash_index_path = x;
+        }
+        if let Some(x) = var("JOURNAL_PATH") {
+            self.journal_path = x;
+        }
+        if let Some(x) = var("REPORT_DIR") {
+            self.report_dir = x;
+        }
+        if let Some(x) = var("METRICS_ADDR") {
+            self.metrics_addr = Some(x);
+        }
+        if let Some(x) = var("LOG_FORMAT") {
+            self.log_format = parse_env("LOG_FORMAT", &x)?;
+        }
+        if let Some(x) = var("STARTUP_DELAY") {
+            self.startup_delay = parse_env("STARTUP_DELAY", &x)?;
+        }
+        if let Some(x) = var("LANGUAGES") {
+            self.languages = x
+                .split(',')
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty())
+                .collect();
+        }
+        if let Some(x) = var("PER_PAGE") {
+            self.sync.per_page = parse_env("PER_PAGE", &x)?;
+        }
+        if let Some(x) = var("LATENCY") {
+            self.sync.latency = parse_env("LATENCY", &x)?;
+        }
+        if let Some(x) = var("THREADS") {
+            self.concurrency.threads = parse_env("THREADS", &x)?;
+        }
+        if let Some(x) = var("IMAGE_THREADS") {
+            self.concurrency.images = parse_env("IMAGE_THREADS", &x)?;
+        }
+        if let Some(x) = var("IMAGE_FORMATS") {
+            self.images.formats = x
+                .split(',')
+                .filter(|x| !x.trim().is_empty())
+                .map(|x| parse_env("IMAGE_FORMATS", x))
+                .collect::<anyhow::Result<_>>()?;
+        }
+        if let Some(x) = var("THUMBNAIL_SIZES") {
+            self.images.thumbnail_sizes = x
+                .split(',')
+                .filter(|x| !x.trim().is_empty())
+                .map(|x| parse_env("THUMBNAIL_SIZES", x))
+                .collect::<anyhow::Result<_>>()?;
+        }
+        if let Some(x) = var("TRANSCODE_FORMAT") {
+            self.transcode.format = Some(parse_env("TRANSCODE_FORMAT", &x)?);
+        }
+        if let Some(x) = var("TRANSCODE_QUALITY") {
+            self.transcode.quality = parse_env("TRANSCODE_QUALITY", &x)?;
+        }
+        if let Some(x) = var("TRANSCODE_MAX_EDGE") {
+            self.transcode.max_edge = Some(parse_env("TRANSCODE_MAX_EDGE", &x)?);
+        }
+        if let Some(x) = var("UPSTREAM_LTN_URL") {
+            self.upstream.ltn = x;
+        }
+        if let Some(x) = var("UPSTREAM_HITOMI_URL") {
+            self.upstream.hitomi = x;
+        }
+        if let Some(x) = var("UPSTREAM_THUMBNAIL_URL") {
+            self.upstream.thumbnail = x;
+        }
+        if let Some(x) = var("UPSTREAM_IMAGE_URL") {
+            self.upstream.image = x;
+        }
+
+        Ok(())
+    }
+
+    pub fn apply_opt(&mut self, opt: &Opt) {
+        if let Some(ref x) = opt.madome_url {
+            self.madome_url = x.clone();
+        }
+        if let Some(ref x) = opt.file_repository_url {
+            self.file_repository_url = x.clone();
+        }
+        if let Some(ref x) = opt.token_path {
+            self.token_path = x.clone();
+        }
+        if let Some(ref x) = opt.fail_store_path {
+            self.fail_store_path = x.clone();
+        }
+        if let Some(ref x) = opt.cursor_path {
+            self.cursor_path = x.clone();
+        }
+        if let Some(ref x) = opt.progress_dir {
+            self.progress_dir = x.clone();
+        }
+        if let Some(ref x) = opt.hash_index_path {
+            self.hash_index_path = x.clone();
+        }
+        if let Some(ref x) = opt.journal_path {
+            self.journal_path = x.clone();
+        }
+        if let Some(ref x) = opt.report_dir {
+            self.report_dir = x.clone();
+        }
+        if let Some(ref x) = opt.metrics_addr {
+            self.metrics_addr = Some(x.clone());
+        }
+        if let Some(x) = opt.log_format {
+            self.log_format = x;
+        }
+        if let Some(x) = opt.threads {
+            self.concurrency.threads = x;
+        }
+        if !opt.languages.is_empty() {
+            self.languages = opt.languages.clone();
+        }
+
+        match opt.command {
+            Command::SyncLatest {
+                ref paging,
+                latency,
+                ..
+            } => {
+                if let Some(x) = paging.per_page {
+                    self.sync.per_page = x;
+                }
+                if let Some(x) = latency {
+                    self.sync.latency = x;
+                }
+            }
+            Command::Backfill { ref paging, .. } => {
+                if let Some(x) = paging.per_page {
+                    self.sync.per_page = x;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        Url::parse(&self.madome_url)
+            .map_err(|err| anyhow::Error::msg(format!("madome_url: {}", err)))?;
+        Url::parse(&self.file_repository_url)
+            .map_err(|err| anyhow::Error::msg(format!("file_repository_url: {}", err)))?;
+
+        for (name, url) in self.upstream.iter() {
+            Url::parse(&url.replace("{subdomain}", "aa"))
+                .map_err(|err| anyhow::Error::msg(format!("upstream.{}: {}", name, err)))?;
+        }
+        if !self.upstream.image.contains("{subdomain}") {
+            return Err(anyhow::Error::msg(
+                "upstream.image: must contain `{subdomain}`",
+            ));
+        }
+
+        if self.languages.is_empty() {
+            return Err(anyhow::Error::msg("languages: must not be empty"));
+        }
+        if let Some(language) = self
+            .languages
+            .iter()
+            .find(|language| !LANGUAGES.contains(&language.as_str()))
+        {
+            return Err(anyhow::Error::msg(format!(
+                "languages: unknown language `{}`",
+                language
+            )));
+        }
+        if self.languages.len() > 1 && self.languages.iter().any(|x| x == "all") {
+            return Err(anyhow::Error::msg(
+                "languages: `all` can't be used with other languages",
+            ));
+        }
+        if self.sync.per_page == 0 {
+            return Err(anyhow::Error::msg("sync.per_page: must be greater than 0"));
+        }
+        if self.concurrency.threads == 0 {
+            return Err(anyhow::Error::msg(
+                "concurrency.threads: must be greater than 0",
+            ));
+        }
+        if self.concurrency.images == 0 {
+            return Err(anyhow::Error::msg(
+                "concurrency.images: must be greater than 0",
+            ));
+        }
+        if self.images.formats.is_empty() {
+            return Err(anyhow::Error::msg("images.formats: must not be empty"));
+        }
+        if !(1..=100).contains(&self.transcode.quality) {
+            return Err(anyhow::Error::msg(
+                "transcode.quality: must be between 1 and 100",
+            ));
+        }
+        if self.transcode.max_edge == Some(0) {
+            return Err(anyhow::Error::msg(
+                "transcode.max_edge: must be greater than 0",
+            ));
+        }
+        if self.retry.iter().any(|policy| policy.max_attempts == 0) {
+            return Err(anyhow::Error::msg(
+                "retry.*.max_attempts: must be greater than 0",
+            ));
+        }
+        if self.retry.iter().any(|policy| policy.multiplier < 1.0) {
+            return Err(anyhow::Error::msg(
+                "retry.*.multiplier: must be 1.0 or greater",
+            ));
+        }
+        if self
+            .retry
+            .iter()
+            .any(|policy| !(0.0..=1.0).contains(&policy.jitter))
+        {
+            return Err(anyhow::Error::msg(
+                "retry.*.jitter: must be between 0.0 and 1.0",
+            ));
+        }
+        if let Some(kind) = self
+            .retry
+            .iter()
+            .filter_map(|policy| policy.retryable.as_ref())
+            .flatten()
+            .find(|kind| kind.is_fatal())
+        {
+            return Err(anyhow::Error::msg(format!(
+                "retry.*.retryable: {} is fatal, can't be retried",
+                kind
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
+
+fn parse_env<T>(key: &str, value: &str) -> anyhow::Result<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    value.parse::<T>().map_err(|err| {
+        anyhow::Error::msg(format!(
+            "Can't parse {} from environment variables: {}",
+            key, err
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use structopt::StructOpt;
+
+    use super::Config;
+    use crate::cli::Opt;
+    use crate::error::ErrorKind;
+    use crate::parser::ImageFormat;
+    use crate::thumbnail::ThumbnailSize;
+    use crate::transcode::TranscodeFormat;
+
+    #[test]
+    fn parse_partial_file() -> anyhow::Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            madome_url = "https://staging.api.madome.app"
+            languages = ["korean", "english"]
+
+            [retry.add_images]
+            max_attempts = 5
+            "#,
+        )?;
+
+        assert_eq!("https://staging.api.madome.app", config.madome_url);
+        assert_eq!("https://file.madome.app", config.file_repository_url);
+        assert_eq!(vec!["korean", "english"], config.languages);
+        assert_eq!(5, config.retry.add_images.max_attempts);
+        assert_eq!(1000, config.retry.add_images.backoff);
+        assert_eq!(3, config.retry.add_book.max_attempts);
+        assert_eq!(0.5, config.retry.add_book.jitter);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unknown_field() {
+        let r = toml::from_str::<Config>("madome_uri = \"https://api.madome.app\"");
+
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn override_file_by_env_and_cli() -> anyhow::Result<()> {
+        let mut config: Config = toml::from_str(
+            r#"
+            madome_url = "https://file.example"
+            token_path = "./file.token"
+
+            [sync]
+            per_page = 10
+            "#,
+        )?;
+
+        let env = [
+            ("MADOME_URL", "https://env.example"),
+            ("PER_PAGE", "50"),
+            ("LANGUAGES", "korean, japanese"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect::<HashMap<_, _>>();
+
+        config.apply_env(|key| env.get(key).cloned())?;
+
+        let opt = Opt::from_iter_safe(&[
+            "madome-synchronizer",
+            "--madome-url",
+            "https://cli.example",
+            "backfill",
+            "--per-page",
+            "100",
+        ])?;
+
+        config.apply_opt(&opt);
+
+        assert_eq!("https://cli.example", config.madome_url);
+        assert_eq!("./file.token", config.token_path);
+        assert_eq!(100, config.sync.per_page);
+        assert_eq!(vec!["korean", "japanese"], config.languages);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_env() {
+        let mut config = Config::default();
+
+        let r = config.apply_env(|key| match key {
+            "LATENCY" => Some("an hour".to_string()),
+            _ => None,
+        });
+
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+
+        config.retry.add_book.max_attempts = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_retry_policy() {
+        let mut config = Config::default();
+
+        config.retry.add_images.jitter = 1.5;
+        assert!(config.validate().is_err());
+
+        config.retry.add_images.jitter = 0.5;
+        config.retry.add_images.multiplier = 0.5;
+        assert!(config.validate().is_err());
+
+        config.retry.add_images.multiplier = 1.0;
+        config.retry.add_images.retryable = Some(vec![ErrorKind::MadomeUnauthorized]);
+        assert!(config.validate().is_err());
+
+        config.retry.add_images.retryable = Some(vec![ErrorKind::UpstreamNotFound]);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn exponential_backoff() -> anyhow::Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            [retry.add_images]
+            max_attempts = 5
+            backoff = 1000
+            max_backoff = 5000
+            jitter = 0.5
+            retryable = ["upstream_unavailable", "network"]
+            "#,
+        )?;
+        let policy = &config.retry.add_images;
+
+        assert_eq!(Duration::from_millis(1000), policy.delay(1, 0.0));
+        assert_eq!(Duration::from_millis(2000), policy.delay(2, 0.0));
+        assert_eq!(Duration::from_millis(4000), policy.delay(3, 0.0));
+        assert_eq!(Duration::from_millis(5000), policy.delay(4, 0.0));
+        // jitter
+        assert_eq!(Duration::from_millis(1000), policy.delay(2, 0.999_999_9));
+        assert_eq!(Duration::from_millis(1500), policy.delay(2, 0.5));
+
+        assert!(policy.is_retryable(ErrorKind::Network));
+        assert!(!policy.is_retryable(ErrorKind::CorruptImage));
+        // default kinds
+        assert!(config.retry.add_book.is_retryable(ErrorKind::CorruptImage));
+        assert!(!config
+            .retry
+            .add_book
+            .is_retryable(ErrorKind::UpstreamNotFound));
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_languages() {
+        let mut config = Config::default();
+
+        config.languages = vec!["all".to_string()];
+        assert!(config.validate().is_ok());
+
+        config.languages = vec!["all".to_string(), "korean".to_string()];
+        assert!(config.validate().is_err());
+
+        config.languages = vec!["korea".to_string()];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn override_upstream() -> anyhow::Result<()> {
+        let mut config: Config = toml::from_str(
+            r#"
+            [upstream]
+            ltn = "http://127.0.0.1:8080/ltn"
+            "#,
+        )?;
+
+        config.apply_env(|key| match key {
+            "UPSTREAM_IMAGE_URL" => Some("http://127.0.0.1:8080/{subdomain}".to_string()),
+            _ => None,
+        })?;
+
+        assert_eq!("http://127.0.0.1:8080/ltn", config.upstream.ltn);
+        assert_eq!("https://hitomi.la", config.upstream.hitomi);
+        assert!(config.validate().is_ok());
+
+        config.upstream.image = "http://127.0.0.1:8080".to_string();
+        assert!(config.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn transcode() -> anyhow::Result<()> {
+        let mut config = Config::default();
+
+        assert_eq!(None, config.transcode.transcoder());
+
+        config.apply_env(|key| match key {
+            "TRANSCODE_FORMAT" => Some("jpeg".to_string()),
+            "TRANSCODE_MAX_EDGE" => Some("2560".to_string()),
+            _ => None,
+        })?;
+
+        assert_eq!(Some(TranscodeFormat::Jpeg), config.transcode.format);
+        assert_eq!(Some(2560), config.transcode.max_edge);
+        assert!(config.transcode.transcoder().is_some());
+        assert!(config.validate().is_ok());
+
+        config.transcode.quality = 0;
+        assert!(config.validate().is_err());
+
+        // webp of `image` is lossless only
+        assert!(config
+            .apply_env(|key| match key {
+                "TRANSCODE_FORMAT" => Some("webp".to_string()),
+                _ => None,
+            })
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn image_formats() -> anyhow::Result<()> {
+        let mut config: Config = toml::from_str(
+            r#"
+            [images]
+            formats = ["avif", "webp"]
+            thumbnail_sizes = ["small", "large"]
+            "#,
+        )?;
+
+        assert_eq!(
+            vec![ImageFormat::Avif, ImageFormat::Webp],
+            config.images.formats
+        );
+        assert_eq!(
+            vec![ThumbnailSize::Small, ThumbnailSize::Large],
+            config.images.thumbnail_sizes
+        );
+
+        config.apply_env(|key| match key {
+            "IMAGE_FORMATS" => Some("webp, original".to_string()),
+            _ => None,
+        })?;
+
+        assert_eq!(
+            vec![ImageFormat::Webp, ImageFormat::Original],
+            config.images.formats
+        );
+
+        let r = config.apply_env(|key| match key {
+            "IMAGE_FORMATS" => Some("jpeg".to_string()),
+            _ => None,
+        });
+
+        assert!(r.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn to_toml_round_trip() -> anyhow::Result<()> {
+        let config = Config::default();
+
+        let r: Config = toml::from_str(&config.to_toml()?)?;
+
+        assert_eq!(config, r);
+
+        Ok(())
+    }
+}
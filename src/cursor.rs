@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow;
+
+use crate::parser::NozomiSource;
+
+/// # Cursor
+/// Page cursor of a nozomi source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub source: NozomiSource,
+    pub page: usize,
+    prev_last_id: u32,
+    done: bool,
+    /// Newest ID synchronized by previous cycles
+    mark: Option<u32>,
+    /// Newest ID seen in current cycle
+    newest_id: Option<u32>,
+}
+
+impl Cursor {
+    pub fn new(source: NozomiSource, page: usize) -> Self {
+        Self {
+            source,
+            page,
+            prev_last_id: 0,
+            done: false,
+            mark: None,
+            newest_id: None,
+        }
+    }
+
+    pub fn mark(&self) -> Option<u32> {
+        self.mark
+    }
+
+    /// Filters IDs newer than the high-water mark, and remembers the newest ID of current cycle.
+    /// Returns whether the IDs reached the high-water mark
+    ///
+    /// Every IDs are newer if the cursor has no high-water mark
+    pub fn newer_ids(&mut self, ids: Vec<u32>) -> (Vec<u32>, bool) {
+        if let Some(id) = ids.iter().max() {
+            self.newest_id = self.newest_id.max(Some(*id));
+        }
+
+        match self.mark {
+            Some(mark) => {
+                let reached = ids.is_empty() || ids.iter().any(|id| *id <= mark);
+                let ids = ids.into_iter().filter(|id| *id > mark).collect();
+
+                (ids, reached)
+            }
+            None => (ids, false),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn advance(&mut self) {
+        self.page += 1;
+    }
+
+    pub fn finish(&mut self) {
+        self.done = true;
+    }
+
+    /// Checks the end of nozomi source by the IDs of current page,
+    /// and finishes the cursor if it is the end
+    pub fn is_end(&mut self, ids: &[u32]) -> bool {
+        let curr_last_id = match ids.last() {
+            Some(id) => *id,
+            None => {
+                self.finish();
+                return true;
+            }
+        };
+
+        if curr_last_id == self.prev_last_id {
+            self.finish();
+            return true;
+        }
+
+        self.prev_last_id = curr_last_id;
+
+        false
+    }
+
+    fn reset(&mut self, page: usize) {
+        self.page = page;
+        self.prev_last_id = 0;
+        self.done = false;
+        self.mark = self.mark.max(self.newest_id.take());
+    }
+}
+
+/// # Cursors
+/// Cursors of each nozomi source, iterated by round robin
+pub struct Cursors {
+    initial_page: usize,
+    inner: Vec<Cursor>,
+}
+
+impl Cursors {
+    pub fn new(sources: Vec<NozomiSource>, initial_page: usize) -> Self {
+        Self {
+            initial_page,
+            inner: sources
+                .into_iter()
+                .map(|source| Cursor::new(source, initial_page))
+                .collect(),
+        }
+    }
+
+    /// Not finished cursors, in order of sources
+    pub fn active(&mut self) -> impl Iterator<Item = &mut Cursor> {
+        self.inner.iter_mut().filter(|cursor| !cursor.is_done())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.inner.iter().all(Cursor::is_done)
+    }
+
+    pub fn with_marks(mut self, marks: &HighWaterMarks) -> Self {
+        for cursor in self.inner.iter_mut() {
+            cursor.mark = marks.get(&cursor.source);
+        }
+        self
+    }
+
+    /// Moves the high-water mark of each cursor to the newest ID of current cycle
+    pub fn reset(&mut self) {
+        let initial_page = self.initial_page;
+
+        for cursor in self.inner.iter_mut() {
+            cursor.reset(initial_page);
+        }
+    }
+}
+
+/// # High-water Marks
+/// Newest ID synchronized of each nozomi source
+///
+/// ```json
+/// { "index-korean": 1744332, "artist/airandou-korean": 1724122 }
+/// ```
+#[derive(Debug, Default, PartialEq)]
+pub struct HighWaterMarks {
+    inner: HashMap<String, u32>,
+}
+
+impl HighWaterMarks {
+    /// Empty if the file doesn't exist
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        if !path.as_ref().exists() {
+            return Ok(Self::default());
+        }
+
+        let text = fs::read_to_string(path)?;
+
+        if text.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        let inner = serde_json::from_str(&text)?;
+
+        Ok(Self { inner })
+    }
+
+    pub fn get(&self, source: &NozomiSource) -> Option<u32> {
+        self.inner.get(&source.to_string()).copied()
+    }
+
+    /// Only moves forward
+    pub fn set(&mut self, source: &NozomiSource, id: u32) {
+        let mark = self.inner.entry(source.to_string()).or_insert(id);
+
+        if *mark < id {
+            *mark = id;
+        }
+    }
+
+    pub fn update(&mut self, cursors: &Cursors) {
+        for cursor in cursors.inner.iter() {
+            if let Some(id) = cursor.mark.max(cursor.newest_id) {
+                self.set(&cursor.source, id);
+            }
+        }
+    }
+
+    pub fn iter(&self) -> std::collections::hash_map::Iter<'_, String, u32> {
+        self.inner.iter()
+    }
+
+    pub fn synchronize(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(&self.inner)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cursor, Cursors, HighWaterMarks};
+    use crate::parser::NozomiSource;
+
+    fn sources(languages: &[&str]) -> Vec<NozomiSource> {
+        languages
+            .iter()
+            .map(|language| NozomiSource::index(*language))
+            .collect()
+    }
+
+    #[test]
+    fn cursor_is_end() {
+        let mut cursor = Cursor::new(NozomiSource::index("korean"), 1);
+
+        assert!(!cursor.is_end(&[30, 20, 10]));
+        cursor.advance();
+        assert!(!cursor.is_end(&[9, 8, 7]));
+        cursor.advance();
+        // out of bounds of nozomi returns the last page again
+        assert!(cursor.is_end(&[9, 8, 7]));
+
+        assert!(cursor.is_done());
+        assert_eq!(3, cursor.page);
+    }
+
+    #[test]
+    fn cursor_is_end_by_empty_page() {
+        let mut cursor = Cursor::new(NozomiSource::index("korean"), 1);
+
+        assert!(cursor.is_end(&[]));
+        assert!(cursor.is_done());
+    }
+
+    #[test]
+    fn cursors_interleave() {
+        let mut cursors = Cursors::new(sources(&["korean", "english", "japanese"]), 1);
+        let mut visited = vec![];
+
+        while !cursors.is_done() {
+            for cursor in cursors.active() {
+                visited.push((cursor.source.language().to_string(), cursor.page));
+
+                if cursor.source.language() == "english" && cursor.page == 2 {
+                    cursor.finish();
+                } else if cursor.page == 3 {
+                    cursor.finish();
+                } else {
+                    cursor.advance();
+                }
+            }
+        }
+
+        let expected = vec![
+            ("korean", 1),
+            ("english", 1),
+            ("japanese", 1),
+            ("korean", 2),
+            ("english", 2),
+            ("japanese", 2),
+            ("korean", 3),
+            ("japanese", 3),
+        ]
+        .into_iter()
+        .map(|(language, page)| (language.to_string(), page))
+        .collect::<Vec<_>>();
+
+        assert_eq!(expected, visited);
+    }
+
+    #[test]
+    fn cursors_reset() {
+        let mut cursors = Cursors::new(sources(&["korean", "english"]), 2);
+
+        for cursor in cursors.active() {
+            cursor.advance();
+            cursor.finish();
+        }
+        assert!(cursors.is_done());
+
+        cursors.reset();
+
+        assert!(!cursors.is_done());
+        assert!(cursors.active().all(|cursor| cursor.page == 2));
+    }
+
+    #[test]
+    fn cursor_newer_ids_without_mark() {
+        let mut cursor = Cursor::new(NozomiSource::index("korean"), 1);
+
+        assert_eq!(
+            (vec![30, 20, 10], false),
+            cursor.newer_ids(vec![30, 20, 10])
+        );
+    }
+
+    #[test]
+    fn cursor_newer_ids_until_mark() {
+        let mut marks = HighWaterMarks::default();
+        marks.set(&NozomiSource::index("korean"), 15);
+
+        let mut cursors = Cursors::new(sources(&["korean"]), 1).with_marks(&marks);
+
+        for cursor in cursors.active() {
+            assert_eq!(
+                (vec![40, 35, 30], false),
+                cursor.newer_ids(vec![40, 35, 30])
+            );
+            cursor.advance();
+            assert_eq!((vec![20], true), cursor.newer_ids(vec![20, 15, 10]));
+            cursor.finish();
+        }
+
+        marks.update(&cursors);
+        cursors.reset();
+
+        assert_eq!(Some(40), marks.get(&NozomiSource::index("korean")));
+
+        for cursor in cursors.active() {
+            assert_eq!(Some(40), cursor.mark());
+            assert_eq!((vec![], true), cursor.newer_ids(vec![40, 35, 30]));
+        }
+    }
+
+    #[test]
+    fn high_water_marks_only_move_forward() {
+        let source = NozomiSource::index("korean");
+        let mut marks = HighWaterMarks::default();
+
+        marks.set(&source, 20);
+        marks.set(&source, 10);
+
+        assert_eq!(Some(20), marks.get(&source));
+        assert_eq!(None, marks.get(&NozomiSource::index("english")));
+    }
+}
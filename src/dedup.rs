@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow;
+use log::debug;
+
+/// # Hash Index
+/// Uploaded images by hash of hitomi, galleries sharing images reference the uploaded object
+///
+/// Append-only `{hash}\t{url_path}` lines, the last line of a hash wins
+///
+/// `5997bc...b9e1\timage/library/1721169/1.webp`
+pub struct HashIndex {
+    path: PathBuf,
+    inner: Mutex<HashMap<String, String>>,
+    /// Opened by the first append, lines are appended without locking `inner`
+    file: Mutex<Option<File>>,
+}
+
+impl HashIndex {
+    /// Empty if the file doesn't exist, lines without tab are ignored
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let inner = match fs::read_to_string(&path) {
+            Ok(text) => text
+                .lines()
+                .filter_map(|line| {
+                    let mut line = line.splitn(2, '\t');
+                    let hash = line.next()?.trim().to_string();
+                    let url_path = line.next()?.trim().to_string();
+                    Some((hash, url_path))
+                })
+                .filter(|(hash, url_path)| !hash.is_empty() && !url_path.is_empty())
+                .collect(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        debug!("HashIndex: {} hashes", inner.len());
+
+        Ok(Self {
+            path,
+            inner: Mutex::new(inner),
+            file: Mutex::new(None),
+        })
+    }
+
+    /// url_path uploaded with `hash`
+    pub fn get(&self, hash: &str) -> Option<String> {
+        self.inner.lock().unwrap().get(hash).cloned()
+    }
+
+    /// Appends to the file, so it survives crashes
+    pub fn insert(&self, hash: &str, url_path: &str) -> anyhow::Result<()> {
+        {
+            let mut inner = self.inner.lock().unwrap();
+
+            if inner.get(hash).map(String::as_str) == Some(url_path) {
+                return Ok(());
+            }
+
+            inner.insert(hash.to_string(), url_path.to_string());
+        }
+
+        let mut file = self.file.lock().unwrap();
+
+        if file.is_none() {
+            if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+
+        if let Some(ref mut file) = *file {
+            writeln!(file, "{}\t{}", hash, url_path)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::HashIndex;
+
+    #[test]
+    fn load_appended() -> anyhow::Result<()> {
+        let path = env::temp_dir().join(format!("madome-hash-index-{}.txt", std::process::id()));
+
+        let index = HashIndex::load(&path)?;
+
+        assert!(index.is_empty());
+
+        index.insert("abc", "image/library/1/1.jpg")?;
+        index.insert("def", "image/library/1/2.jpg")?;
+        index.insert("abc", "image/library/2/1.webp")?;
+
+        // crashed while appending
+        fs::write(&path, format!("{}broken line", fs::read_to_string(&path)?))?;
+
+        let index = HashIndex::load(&path)?;
+
+        assert_eq!(2, index.len());
+        assert_eq!(Some("image/library/2/1.webp".to_string()), index.get("abc"));
+        assert_eq!(Some("image/library/1/2.jpg".to_string()), index.get("def"));
+        assert_eq!(None, index.get("ghi"));
+
+        fs::remove_file(&path)?;
+
+        Ok(())
+    }
+}
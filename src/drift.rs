@@ -0,0 +1,122 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use anyhow;
+
+use crate::error::SyncError;
+
+/// Gallery IDs shown in a line of summary
+const MAX_SHOWN_IDS: usize = 5;
+
+/// # Layout Drift
+/// Fields of hitomi failed by `SyncError::ParseLayoutChanged` in a run,
+/// many galleries failed on the same field means a redesign of hitomi
+#[derive(Debug, Default)]
+pub struct LayoutDrift {
+    /// (field, selector) => gallery IDs
+    inner: BTreeMap<(String, String), Vec<u32>>,
+}
+
+impl LayoutDrift {
+    /// Returns whether `err` is a layout change
+    pub fn record(&mut self, err: &anyhow::Error) -> bool {
+        let layout_changed = err.chain().find_map(|cause| match cause.downcast_ref() {
+            Some(SyncError::ParseLayoutChanged {
+                id,
+                field,
+                selector,
+            }) => Some((*id, field, selector)),
+            _ => None,
+        });
+
+        match layout_changed {
+            Some((id, field, selector)) => {
+                let ids = self
+                    .inner
+                    .entry((field.clone(), selector.clone()))
+                    .or_default();
+
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// A line of each field, most failed first
+    ///
+    /// `Tags (ul): 3 galleries, 1724122, 1399900, 1277807`
+    pub fn summary(&self) -> Vec<String> {
+        let mut fields = self.inner.iter().collect::<Vec<_>>();
+        fields.sort_by_key(|(_, ids)| Reverse(ids.len()));
+
+        fields
+            .into_iter()
+            .map(|((field, selector), ids)| {
+                let shown = ids
+                    .iter()
+                    .take(MAX_SHOWN_IDS)
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let more = if ids.len() > MAX_SHOWN_IDS {
+                    ", ..."
+                } else {
+                    ""
+                };
+
+                format!(
+                    "{} ({}): {} galleries, {}{}",
+                    field,
+                    selector,
+                    ids.len(),
+                    shown,
+                    more
+                )
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.inner.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LayoutDrift;
+    use crate::error::SyncError;
+
+    #[test]
+    fn summary_of_layout_changes() {
+        let mut drift = LayoutDrift::default();
+
+        for id in &[1, 2, 3, 3] {
+            let err = SyncError::layout_changed(*id, "Tags", "ul").into();
+            assert!(drift.record(&err));
+        }
+
+        let err = SyncError::layout_changed(4, "Title", "h1.lillie > a").into();
+        assert!(drift.record(&err));
+
+        assert!(!drift.record(&anyhow::Error::msg("connection reset")));
+
+        assert_eq!(
+            vec![
+                "Tags (ul): 3 galleries, 1, 2, 3",
+                "Title (h1.lillie > a): 1 galleries, 4",
+            ],
+            drift.summary()
+        );
+
+        drift.clear();
+        assert!(drift.is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt::{self, Display, Formatter};
+
+use anyhow;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// # Sync Error
+/// Causes of failed synchronize, retry, skip and abort are decided by [`ErrorKind`]
+///
+/// Parsers and `sync` return it inside of `anyhow::Error`, use [`kind`] to classify
+#[derive(Debug, Error)]
+pub enum SyncError {
+    #[error("Not found in upstream: {url}")]
+    UpstreamNotFound { url: String },
+
+    #[error("Rate limited by upstream: {url}")]
+    UpstreamRateLimited { url: String },
+
+    #[error("Upstream responded {status}: {url}")]
+    UpstreamStatus { url: String, status: u16 },
+
+    #[error("{id}: Layout of hitomi is changed, can't find `{selector}` of {field}")]
+    ParseLayoutChanged {
+        id: u32,
+        field: String,
+        selector: String,
+    },
+
+    #[error("{id}: Can't parse date `{date}`")]
+    InvalidDate { id: u32, date: String },
+
+    #[error("Corrupt image {url}: {reason}")]
+    CorruptImage { url: String, reason: String },
+
+    #[error("Can't get request_data, request() before parse()")]
+    NotRequested,
+
+    #[error("Unauthorized by Madome: {cause}")]
+    MadomeUnauthorized { cause: String },
+
+    #[error("Can't upload {url_path}: {cause}")]
+    UploadFailed { url_path: String, cause: String },
+
+    #[error("No new ids")]
+    NoNewIds,
+}
+
+impl SyncError {
+    /// Error of non-success status of upstream
+    pub fn from_status(url: impl Into<String>, status: StatusCode) -> Self {
+        let url = url.into();
+
+        match status {
+            StatusCode::NOT_FOUND => Self::UpstreamNotFound { url },
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                Self::UpstreamRateLimited { url }
+            }
+            status => Self::UpstreamStatus {
+                url,
+                status: status.as_u16(),
+            },
+        }
+    }
+
+    pub fn layout_changed(id: u32, field: impl Into<String>, selector: impl Into<String>) -> Self {
+        Self::ParseLayoutChanged {
+            id,
+            field: field.into(),
+            selector: selector.into(),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::UpstreamNotFound { .. } => ErrorKind::UpstreamNotFound,
+            Self::UpstreamRateLimited { .. } => ErrorKind::UpstreamRateLimited,
+            Self::UpstreamStatus { status, .. } if *status >= 500 => ErrorKind::UpstreamUnavailable,
+            Self::UpstreamStatus { .. } => ErrorKind::UpstreamRejected,
+            Self::ParseLayoutChanged { .. } => ErrorKind::ParseLayoutChanged,
+            Self::InvalidDate { .. } => ErrorKind::ParseInvalidDate,
+            Self::CorruptImage { .. } => ErrorKind::CorruptImage,
+            Self::NotRequested => ErrorKind::Unknown,
+            Self::MadomeUnauthorized { .. } => ErrorKind::MadomeUnauthorized,
+            Self::UploadFailed { .. } => ErrorKind::UploadFailed,
+            Self::NoNewIds => ErrorKind::NoNewIds,
+        }
+    }
+}
+
+/// Names of `retry.*.retryable` are same as [`ErrorKind::as_str`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    UpstreamNotFound,
+    UpstreamRateLimited,
+    /// 5xx of upstream
+    UpstreamUnavailable,
+    /// 4xx of upstream except 404 and 429
+    UpstreamRejected,
+    /// Connection or timeout
+    Network,
+    ParseLayoutChanged,
+    /// Date of hitomi in unknown format
+    ParseInvalidDate,
+    /// Empty, HTML, truncated or mismatched image
+    CorruptImage,
+    MadomeUnauthorized,
+    UploadFailed,
+    NoNewIds,
+    Unknown,
+}
+
+impl ErrorKind {
+    /// May succeed by next attempt, unknown errors such as local I/O errors are not retryable
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            Self::UpstreamNotFound
+                | Self::UpstreamRejected
+                | Self::ParseLayoutChanged
+                | Self::ParseInvalidDate
+                | Self::MadomeUnauthorized
+                | Self::NoNewIds
+                | Self::Unknown
+        )
+    }
+
+    /// Every next synchronize will fail, so stop synchronizing
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::MadomeUnauthorized)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UpstreamNotFound => "upstream_not_found",
+            Self::UpstreamRateLimited => "upstream_rate_limited",
+            Self::UpstreamUnavailable => "upstream_unavailable",
+            Self::UpstreamRejected => "upstream_rejected",
+            Self::Network => "network",
+            Self::ParseLayoutChanged => "parse_layout_changed",
+            Self::ParseInvalidDate => "parse_invalid_date",
+            Self::CorruptImage => "corrupt_image",
+            Self::MadomeUnauthorized => "madome_unauthorized",
+            Self::UploadFailed => "upload_failed",
+            Self::NoNewIds => "no_new_ids",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Kind of the first [`SyncError`] or `reqwest::Error` in the chain of `err`
+///
+/// Transports don't fail by status, so `reqwest::Error` with status is of `madome_client`
+pub fn kind(err: &anyhow::Error) -> ErrorKind {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<SyncError>() {
+            return err.kind();
+        }
+
+        if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            return match err.status() {
+                Some(StatusCode::UNAUTHORIZED) => ErrorKind::MadomeUnauthorized,
+                Some(_) => ErrorKind::Unknown,
+                None => ErrorKind::Network,
+            };
+        }
+    }
+
+    ErrorKind::Unknown
+}
+
+pub fn is_fatal(err: &anyhow::Error) -> bool {
+    kind(err).is_fatal()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+    use reqwest::StatusCode;
+
+    use super::{kind, ErrorKind, SyncError};
+
+    #[test]
+    fn kind_of_status() {
+        let kind_of = |status| SyncError::from_status("https://ltn.hitomi.la", status).kind();
+
+        assert_eq!(ErrorKind::UpstreamNotFound, kind_of(StatusCode::NOT_FOUND));
+        assert_eq!(
+            ErrorKind::UpstreamRateLimited,
+            kind_of(StatusCode::TOO_MANY_REQUESTS)
+        );
+        assert_eq!(
+            ErrorKind::UpstreamUnavailable,
+            kind_of(StatusCode::BAD_GATEWAY)
+        );
+        assert_eq!(ErrorKind::UpstreamRejected, kind_of(StatusCode::FORBIDDEN));
+    }
+
+    #[test]
+    fn kind_of_anyhow_error() {
+        let err = anyhow::Error::new(SyncError::layout_changed(1724122, "ContentURL", "body > a"));
+        assert_eq!(ErrorKind::ParseLayoutChanged, kind(&err));
+
+        let err: anyhow::Error = Err::<(), _>(SyncError::NoNewIds)
+            .context("index-korean")
+            .unwrap_err();
+        assert_eq!(ErrorKind::NoNewIds, kind(&err));
+
+        let err = anyhow::Error::msg("something");
+        assert_eq!(ErrorKind::Unknown, kind(&err));
+    }
+
+    #[test]
+    fn decide_by_kind() {
+        assert!(ErrorKind::UpstreamUnavailable.is_retryable());
+        assert!(!ErrorKind::UpstreamNotFound.is_retryable());
+        assert!(!ErrorKind::ParseLayoutChanged.is_retryable());
+        assert!(!ErrorKind::ParseInvalidDate.is_retryable());
+        assert!(!ErrorKind::Unknown.is_retryable());
+        assert!(ErrorKind::MadomeUnauthorized.is_fatal());
+    }
+}
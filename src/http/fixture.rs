@@ -0,0 +1,202 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow;
+use bytes::Bytes;
+use log::{debug, trace};
+use reqwest::StatusCode;
+
+use super::{Request, Response, Transport};
+
+/// # Fixture Transport
+/// Replays responses from files, or records responses of another transport to files
+///
+/// A response of `https://ltn.hitomi.la/galleryblock/1399900.html` is
+/// `{dir}/ltn.hitomi.la/galleryblock/1399900.html`,
+/// and `Range` header is appended like `{dir}/ltn.hitomi.la/index-korean.nozomi@bytes=0-99`
+///
+/// Status code is `200 OK` unless there is `{path}.status` file,
+/// and missing fixture is `404 Not Found`
+pub struct FixtureTransport {
+    dir: PathBuf,
+    recorder: Option<Arc<dyn Transport>>,
+}
+
+impl FixtureTransport {
+    pub fn replay(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            recorder: None,
+        }
+    }
+
+    /// Sends requests by `inner`, and writes the responses to fixtures
+    pub fn record(dir: impl Into<PathBuf>, inner: Arc<dyn Transport>) -> Self {
+        Self {
+            dir: dir.into(),
+            recorder: Some(inner),
+        }
+    }
+
+    pub fn path(&self, request: &Request) -> PathBuf {
+        let url = request.url.as_str();
+        let url = match url.find("://") {
+            Some(i) => &url[i + 3..],
+            None => url,
+        };
+
+        let mut name = url
+            .split('/')
+            .filter(|x| !x.is_empty())
+            .map(sanitize)
+            .collect::<Vec<_>>()
+            .join("/");
+
+        if let Some(range) = request.header_value("Range") {
+            name.push('@');
+            name.push_str(&sanitize(range));
+        }
+
+        self.dir.join(name)
+    }
+
+    fn status_path(path: &Path) -> PathBuf {
+        let mut status_path = path.as_os_str().to_owned();
+        status_path.push(".status");
+        PathBuf::from(status_path)
+    }
+
+    fn read(&self, path: &Path) -> anyhow::Result<Response> {
+        if !path.exists() {
+            debug!("Missing fixture {}", path.display());
+
+            return Ok(Response {
+                status: StatusCode::NOT_FOUND,
+                body: Bytes::new(),
+            });
+        }
+
+        let status_path = Self::status_path(path);
+
+        let status = if status_path.exists() {
+            StatusCode::from_u16(fs::read_to_string(status_path)?.trim().parse()?)?
+        } else {
+            StatusCode::OK
+        };
+
+        let body = Bytes::from(fs::read(path)?);
+
+        Ok(Response { status, body })
+    }
+
+    fn write(&self, path: &Path, response: &Response) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, &response.body)?;
+
+        if response.status != StatusCode::OK {
+            fs::write(
+                Self::status_path(path),
+                response.status.as_u16().to_string(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Transport for FixtureTransport {
+    fn send(&self, request: Request) -> anyhow::Result<Response> {
+        let path = self.path(&request);
+
+        trace!(
+            "FixtureTransport::send({}) => {}",
+            request.url,
+            path.display()
+        );
+
+        match self.recorder {
+            Some(ref inner) => {
+                let response = inner.send(request)?;
+                self.write(&path, &response)?;
+                Ok(response)
+            }
+            None => self.read(&path),
+        }
+    }
+}
+
+fn sanitize(x: &str) -> String {
+    x.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' | '@' | '=' | ' ' | ':' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use reqwest::StatusCode;
+
+    use super::FixtureTransport;
+    use crate::http::{Request, Response, Transport};
+
+    struct StaticTransport;
+
+    impl Transport for StaticTransport {
+        fn send(&self, request: Request) -> anyhow::Result<Response> {
+            Ok(Response {
+                status: StatusCode::PARTIAL_CONTENT,
+                body: Bytes::from(request.url),
+            })
+        }
+    }
+
+    #[test]
+    fn fixture_path() {
+        let fixture = FixtureTransport::replay("fixtures");
+
+        let request =
+            Request::get("https://ltn.hitomi.la/index-korean.nozomi").header("Range", "bytes=0-99");
+        assert_eq!(
+            "fixtures/ltn.hitomi.la/index-korean.nozomi@bytes=0-99",
+            fixture.path(&request).to_str().unwrap()
+        );
+
+        let request = Request::get("https://hitomi.la/reader/1724122.html?a=1#2");
+        assert_eq!(
+            "fixtures/hitomi.la/reader/1724122.html_a=1_2",
+            fixture.path(&request).to_str().unwrap()
+        );
+    }
+
+    #[test]
+    fn record_and_replay() -> anyhow::Result<()> {
+        let dir = env::temp_dir().join(format!("madome-fixture-{}", std::process::id()));
+        let url = "https://ltn.hitomi.la/galleries/1.js";
+
+        let recorder = FixtureTransport::record(&dir, Arc::new(StaticTransport));
+        let recorded = recorder.send(Request::get(url))?;
+
+        let replayer = FixtureTransport::replay(&dir);
+        let replayed = replayer.send(Request::get(url))?;
+        let missing = replayer.send(Request::get("https://ltn.hitomi.la/galleries/2.js"))?;
+
+        fs::remove_dir_all(&dir)?;
+
+        assert_eq!(recorded, replayed);
+        assert_eq!(StatusCode::PARTIAL_CONTENT, replayed.status);
+        assert_eq!(StatusCode::NOT_FOUND, missing.status);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use anyhow;
+use bytes::Bytes;
+use reqwest::StatusCode;
+
+mod fixture;
+mod reqwest_transport;
+
+pub use fixture::FixtureTransport;
+pub use reqwest_transport::ReqwestTransport;
+
+/// # Transport
+/// Sends HTTP requests to upstream (hitomi.la)
+///
+/// Parsers use [`ReqwestTransport`] by default,
+/// tests replace it with [`FixtureTransport`] to run without network
+pub trait Transport: Send + Sync {
+    fn send(&self, request: Request) -> anyhow::Result<Response>;
+}
+
+pub fn default_transport() -> Arc<dyn Transport> {
+    Arc::new(ReqwestTransport::new())
+}
+
+/// GET request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub fn get(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            headers: vec![],
+        }
+    }
+
+    pub fn header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn header_value(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: StatusCode,
+    pub body: Bytes,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        self.status.is_success()
+    }
+
+    pub fn text(&self) -> anyhow::Result<String> {
+        Ok(String::from_utf8(self.body.to_vec())?)
+    }
+}
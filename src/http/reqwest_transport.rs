@@ -0,0 +1,42 @@
+use anyhow;
+use log::trace;
+use reqwest;
+
+use super::{Request, Response, Transport};
+
+pub struct ReqwestTransport {
+    client: reqwest::blocking::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+}
+
+impl Default for ReqwestTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn send(&self, request: Request) -> anyhow::Result<Response> {
+        trace!("ReqwestTransport::send({})", request.url);
+
+        let mut builder = self.client.get(&request.url);
+
+        for (key, value) in request.headers.iter() {
+            builder = builder.header(key.as_str(), value.as_str());
+        }
+
+        let response = builder.send()?;
+
+        let status = response.status();
+        let body = response.bytes()?;
+
+        Ok(Response { status, body })
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::stage::Stage;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Record {
+    Started { id: u32 },
+    Fulfilled { id: u32, stage: String },
+    Failed { id: u32 },
+    Finished { id: u32 },
+}
+
+/// Fulfilled stages of an id, kept for failed ids so retries skip them
+#[derive(Debug, Default)]
+struct Entry {
+    stages: BTreeSet<u8>,
+    /// Added to fail_store, so not resumed
+    failed: bool,
+}
+
+/// # Journal
+/// Stage transitions of galleries, so synchronizing resumes after crashes or redeploys
+///
+/// Append-only JSON lines, compacted to the incomplete and failed ids on load
+///
+/// ```json
+/// {"event":"started","id":1724122}
+/// {"event":"fulfilled","id":1724122,"stage":"add_image_list"}
+/// {"event":"failed","id":1724122}
+/// {"event":"finished","id":1724122}
+/// ```
+pub struct Journal {
+    path: PathBuf,
+    inner: Mutex<BTreeMap<u32, Entry>>,
+}
+
+impl Journal {
+    /// Replays and compacts the file, lines cut by crashes are ignored
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let journal = Self::read(path)?;
+
+        journal.compact()?;
+
+        Ok(journal)
+    }
+
+    /// Replays the file without writing it, for inspecting a journal in use
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut inner = BTreeMap::<u32, Entry>::new();
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<Record>(line) {
+                Ok(Record::Started { id }) => {
+                    inner.entry(id).or_default().failed = false;
+                }
+                Ok(Record::Fulfilled { id, stage }) => match stage.parse::<Stage>() {
+                    Ok(stage) => {
+                        inner.entry(id).or_default().stages.insert(stage.as_u8());
+                    }
+                    Err(err) => warn!("Journal: {}", err),
+                },
+                Ok(Record::Failed { id }) => {
+                    inner.entry(id).or_default().failed = true;
+                }
+                Ok(Record::Finished { id }) => {
+                    inner.remove(&id);
+                }
+                Err(err) => warn!("Journal: Skip `{}`: {}", line, err),
+            }
+        }
+
+        debug!(
+            "Journal: {} incomplete ids",
+            inner.values().filter(|entry| !entry.failed).count()
+        );
+
+        Ok(Self {
+            path,
+            inner: Mutex::new(inner),
+        })
+    }
+
+    /// Incomplete ids in order, failed ids are retried by fail_store instead
+    pub fn incomplete(&self) -> Vec<u32> {
+        self.inner
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| !entry.failed)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    pub fn is_fulfilled(&self, id: u32, stage: &Stage) -> bool {
+        matches!(
+            self.inner.lock().unwrap().get(&id),
+            Some(entry) if entry.stages.contains(&stage.as_u8())
+        )
+    }
+
+    /// Does nothing if `id` is already incomplete, failed ids keep their fulfilled stages
+    pub fn start(&self, id: u32) -> anyhow::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+
+        if matches!(inner.get(&id), Some(entry) if !entry.failed) {
+            return Ok(());
+        }
+
+        self.append(&Record::Started { id })?;
+        inner.entry(id).or_default().failed = false;
+
+        Ok(())
+    }
+
+    pub fn fulfill(&self, id: u32, stage: &Stage) -> anyhow::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = inner.entry(id).or_default();
+
+        if entry.stages.insert(stage.as_u8()) {
+            self.append(&Record::Fulfilled {
+                id,
+                stage: stage.as_str().to_string(),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// `id` is added to fail_store, so it isn't resumed until it is started again
+    pub fn fail(&self, id: u32) -> anyhow::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(entry) = inner.get_mut(&id).filter(|entry| !entry.failed) {
+            self.append(&Record::Failed { id })?;
+            entry.failed = true;
+        }
+
+        Ok(())
+    }
+
+    /// Both of images and book are synchronized with Madome
+    pub fn finish(&self, id: u32) -> anyhow::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.remove(&id).is_some() {
+            self.append(&Record::Finished { id })?;
+        }
+
+        Ok(())
+    }
+
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+
+        Ok(())
+    }
+
+    /// Rewrites the file with the incomplete and failed ids only
+    fn compact(&self) -> anyhow::Result<()> {
+        let inner = self.inner.lock().unwrap();
+
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut text = String::new();
+
+        for (id, entry) in inner.iter() {
+            text.push_str(&serde_json::to_string(&Record::Started { id: *id })?);
+            text.push('\n');
+
+            for stage in &entry.stages {
+                let record = Record::Fulfilled {
+                    id: *id,
+                    stage: Stage::from(*stage).as_str().to_string(),
+                };
+
+                text.push_str(&serde_json::to_string(&record)?);
+                text.push('\n');
+            }
+
+            if entry.failed {
+                text.push_str(&serde_json::to_string(&Record::Failed { id: *id })?);
+                text.push('\n');
+            }
+        }
+
+        let tmp = self.path.with_extension("tmp");
+
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, &self.path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::Journal;
+    use crate::stage::Stage;
+
+    #[test]
+    fn resume_incomplete() -> anyhow::Result<()> {
+        let path = env::temp_dir().join(format!("madome-journal-{}.jsonl", std::process::id()));
+
+        let journal = Journal::load(&path)?;
+
+        journal.start(1724122)?;
+        journal.fulfill(1724122, &Stage::AddImageList)?;
+        journal.start(1277807)?;
+        journal.fulfill(1277807, &Stage::AddImageList)?;
+        journal.fulfill(1277807, &Stage::AddBook)?;
+        journal.finish(1277807)?;
+        journal.start(1721169)?;
+        journal.fulfill(1721169, &Stage::AddImageList)?;
+        journal.fail(1721169)?;
+
+        // crashed while appending
+        fs::write(
+            &path,
+            format!("{}{{\"event\":\"fulfi", fs::read_to_string(&path)?),
+        )?;
+
+        let journal = Journal::load(&path)?;
+
+        assert_eq!(vec![1724122], journal.incomplete());
+        assert!(journal.is_fulfilled(1724122, &Stage::AddImageList));
+        assert!(!journal.is_fulfilled(1724122, &Stage::AddBook));
+        assert!(!journal.is_fulfilled(1277807, &Stage::AddImageList));
+        // failed ids are not resumed, but keep their stages
+        assert!(journal.is_fulfilled(1721169, &Stage::AddImageList));
+
+        // compacted
+        assert_eq!(5, fs::read_to_string(&path)?.lines().count());
+
+        journal.fulfill(1724122, &Stage::AddBook)?;
+
+        assert_eq!(vec![1724122], Journal::read(&path)?.incomplete());
+        assert_eq!(6, fs::read_to_string(&path)?.lines().count());
+
+        journal.start(1721169)?;
+
+        assert_eq!(vec![1721169, 1724122], journal.incomplete());
+
+        fs::remove_file(&path)?;
+
+        Ok(())
+    }
+}
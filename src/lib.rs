@@ -1,5 +1,43 @@
+pub mod cli;
+
+pub mod config;
+
+pub mod cursor;
+
+pub mod dedup;
+
+pub mod drift;
+
+pub mod error;
+
+pub mod http;
+
+pub mod journal;
+
+pub mod logger;
+
+pub mod metrics;
+
 pub mod parser;
 
+pub mod progress;
+
+pub mod report;
+
 pub mod utils;
 
 pub mod stage;
+
+pub mod subdomain;
+
+pub mod sync;
+
+pub mod tag;
+
+pub mod thumbnail;
+
+pub mod transcode;
+
+pub mod upstream;
+
+pub mod verify;
@@ -0,0 +1,253 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow;
+use env_logger::filter::{Builder, Filter};
+use log::kv::{self, Key, Value, VisitSource, VisitValue};
+use log::{Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use time::OffsetDateTime;
+
+/// Fields of every JSON line, `null` if the event doesn't have it
+const FIELDS: [&str; 7] = [
+    "gallery_id",
+    "stage",
+    "state",
+    "progress",
+    "error_kind",
+    "duration_ms",
+    "language",
+];
+
+/// # Log Format
+/// `text` of env_logger, or a JSON object per line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        }
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            x => Err(anyhow::Error::msg(format!(
+                "Unknown log format `{}`, expected text or json",
+                x
+            ))),
+        }
+    }
+}
+
+/// Both of formats are filtered by `RUST_LOG`
+pub fn init(format: LogFormat) {
+    match format {
+        LogFormat::Text => env_logger::init(),
+        LogFormat::Json => {
+            let logger = JsonLogger {
+                filter: Builder::from_env("RUST_LOG").build(),
+            };
+
+            log::set_max_level(logger.filter.filter());
+            log::set_boxed_logger(Box::new(logger)).expect("Logger is already initialized");
+        }
+    }
+}
+
+/// # Json Logger
+/// Writes records to stderr as JSON lines, key-values of records become fields
+///
+/// ```json
+/// {"timestamp":"2021-01-02T03:04:05Z","level":"INFO","target":"madome_synchronizer::stage",
+/// "message":"1724122: Add Images: Pending: 1 / 3 => 33.33%","gallery_id":1724122,"stage":"add_images",
+/// "state":"pending","progress":33.33,"error_kind":null,"duration_ms":812,"language":"korean"}
+/// ```
+struct JsonLogger {
+    filter: Filter,
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+
+        let line = format(record);
+        let _ = writeln!(io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// JSON line of `record`
+pub fn format(record: &Record) -> String {
+    let mut fields = Map::new();
+
+    fields.insert(
+        "timestamp".to_string(),
+        OffsetDateTime::now_utc()
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .into(),
+    );
+    fields.insert("level".to_string(), record.level().as_str().into());
+    fields.insert("target".to_string(), record.target().into());
+    fields.insert("message".to_string(), record.args().to_string().into());
+
+    for field in FIELDS.iter() {
+        fields.insert(field.to_string(), JsonValue::Null);
+    }
+
+    let mut visitor = Fields(&mut fields);
+    let _ = record.key_values().visit(&mut visitor);
+
+    JsonValue::Object(fields).to_string()
+}
+
+struct Fields<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let mut json = Json(JsonValue::Null);
+        value.visit(&mut json)?;
+
+        self.0.insert(key.as_str().to_string(), json.0);
+
+        Ok(())
+    }
+}
+
+struct Json(JsonValue);
+
+impl<'v> VisitValue<'v> for Json {
+    fn visit_any(&mut self, value: Value) -> Result<(), kv::Error> {
+        self.0 = value.to_string().into();
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), kv::Error> {
+        self.0 = JsonValue::Null;
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::kv::{ToValue, Value};
+    use log::{Level, Record};
+    use serde_json::Value as JsonValue;
+
+    use super::{format, LogFormat};
+
+    #[test]
+    fn format_stage_event() -> anyhow::Result<()> {
+        let kvs: [(&str, Value); 5] = [
+            ("gallery_id", Value::from(1724122u32)),
+            ("stage", Value::from("add_images")),
+            ("state", Value::from("pending")),
+            ("progress", Value::from(50.0)),
+            ("language", None::<&str>.to_value()),
+        ];
+
+        let line = format(
+            &Record::builder()
+                .level(Level::Info)
+                .target("madome_synchronizer::stage")
+                .key_values(&kvs)
+                .args(format_args!("1724122: Add Images: Pending: 1 / 2 => 50%"))
+                .build(),
+        );
+        let event = serde_json::from_str::<JsonValue>(&line)?;
+
+        assert_eq!("INFO", event["level"]);
+        assert_eq!(
+            "1724122: Add Images: Pending: 1 / 2 => 50%",
+            event["message"]
+        );
+        assert_eq!(1724122, event["gallery_id"]);
+        assert_eq!("add_images", event["stage"]);
+        assert_eq!("pending", event["state"]);
+        assert_eq!(50.0, event["progress"]);
+        assert_eq!(JsonValue::Null, event["error_kind"]);
+        assert_eq!(JsonValue::Null, event["duration_ms"]);
+        assert_eq!(JsonValue::Null, event["language"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn format_plain_record() -> anyhow::Result<()> {
+        let line = format(
+            &Record::builder()
+                .level(Level::Warn)
+                .args(format_args!("Layout drift: files"))
+                .build(),
+        );
+        let event = serde_json::from_str::<JsonValue>(&line)?;
+
+        assert_eq!("WARN", event["level"]);
+        assert_eq!(JsonValue::Null, event["gallery_id"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_format() -> anyhow::Result<()> {
+        assert_eq!(LogFormat::Json, "json".parse()?);
+        assert!("yaml".parse::<LogFormat>().is_err());
+
+        Ok(())
+    }
+}
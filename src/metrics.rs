@@ -0,0 +1,231 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow;
+use log::{info, warn};
+use prometheus::core::Collector;
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tiny_http::{Header, Response, Server};
+
+use crate::stage::Stage;
+
+const NAMESPACE: &str = "madome_synchronizer";
+
+/// # Outcome
+/// Label of `stages_total`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Fulfilled,
+    Retry,
+    Error,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Fulfilled => "fulfilled",
+            Self::Retry => "retry",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// # Metrics
+/// Prometheus metrics of synchronizing, served by [`serve`]
+pub struct Metrics {
+    registry: Registry,
+    /// stage, outcome
+    stages: IntCounterVec,
+    /// stage
+    stage_duration: HistogramVec,
+    downloaded_bytes: Histogram,
+    uploaded_bytes: Histogram,
+    in_flight_ids: IntGauge,
+    fail_store_ids: IntGauge,
+    /// source, language
+    nozomi_page: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> anyhow::Result<Self> {
+        let opts = |name: &str, help: &str| Opts::new(name, help).namespace(NAMESPACE);
+        let histogram_opts =
+            |name: &str, help: &str| HistogramOpts::new(name, help).namespace(NAMESPACE);
+
+        // 1KiB ~ 32MiB
+        let bytes_buckets = exponential_buckets(1024.0, 2.0, 16)?;
+
+        let metrics = Self {
+            registry: Registry::new(),
+            stages: IntCounterVec::new(
+                opts("stages_total", "Stage calls by outcome"),
+                &["stage", "outcome"],
+            )?,
+            stage_duration: HistogramVec::new(
+                histogram_opts(
+                    "stage_duration_seconds",
+                    "Duration of stage calls including retries",
+                )
+                .buckets(exponential_buckets(0.05, 2.0, 12)?),
+                &["stage"],
+            )?,
+            downloaded_bytes: Histogram::with_opts(
+                histogram_opts("downloaded_bytes", "Bytes of downloaded images")
+                    .buckets(bytes_buckets.clone()),
+            )?,
+            uploaded_bytes: Histogram::with_opts(
+                histogram_opts("uploaded_bytes", "Bytes of uploaded files").buckets(bytes_buckets),
+            )?,
+            in_flight_ids: IntGauge::with_opts(opts(
+                "in_flight_ids",
+                "Galleries being synchronized",
+            ))?,
+            fail_store_ids: IntGauge::with_opts(opts("fail_store_ids", "Galleries in fail_store"))?,
+            nozomi_page: IntGaugeVec::new(
+                opts("nozomi_page", "Current page of nozomi sources"),
+                &["source", "language"],
+            )?,
+        };
+
+        let collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(metrics.stages.clone()),
+            Box::new(metrics.stage_duration.clone()),
+            Box::new(metrics.downloaded_bytes.clone()),
+            Box::new(metrics.uploaded_bytes.clone()),
+            Box::new(metrics.in_flight_ids.clone()),
+            Box::new(metrics.fail_store_ids.clone()),
+            Box::new(metrics.nozomi_page.clone()),
+        ];
+
+        for collector in collectors {
+            metrics.registry.register(collector)?;
+        }
+
+        Ok(metrics)
+    }
+
+    pub fn observe_stage(&self, stage: &Stage, outcome: Outcome) {
+        self.stages
+            .with_label_values(&[stage.as_str(), outcome.as_str()])
+            .inc();
+    }
+
+    pub fn observe_stage_duration(&self, stage: &Stage, secs: f64) {
+        self.stage_duration
+            .with_label_values(&[stage.as_str()])
+            .observe(secs);
+    }
+
+    pub fn observe_downloaded(&self, bytes: usize) {
+        self.downloaded_bytes.observe(bytes as f64);
+    }
+
+    pub fn observe_uploaded(&self, bytes: usize) {
+        self.uploaded_bytes.observe(bytes as f64);
+    }
+
+    /// Decreased when the guard is dropped
+    pub fn in_flight(&self) -> InFlight<'_> {
+        self.in_flight_ids.inc();
+        InFlight(&self.in_flight_ids)
+    }
+
+    pub fn set_fail_store_ids(&self, len: usize) {
+        self.fail_store_ids.set(len as i64);
+    }
+
+    pub fn set_nozomi_page(&self, source: &str, language: &str, page: usize) {
+        self.nozomi_page
+            .with_label_values(&[source, language])
+            .set(page as i64);
+    }
+
+    /// Text format of prometheus
+    pub fn encode(&self) -> anyhow::Result<String> {
+        let mut buf = vec![];
+
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+
+        Ok(String::from_utf8(buf)?)
+    }
+}
+
+pub struct InFlight<'a>(&'a IntGauge);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Serves `GET /metrics` on `addr` in background, returns the bound address
+pub fn serve(addr: &str, metrics: Arc<Metrics>) -> anyhow::Result<SocketAddr> {
+    let server = Server::http(addr).map_err(|err| anyhow::Error::msg(err.to_string()))?;
+    let addr = server.server_addr();
+
+    info!("Serve metrics on http://{}/metrics", addr);
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = match request.url() {
+                "/metrics" => match metrics.encode() {
+                    Ok(text) => Response::from_string(text).with_header(
+                        "Content-Type: text/plain; version=0.0.4"
+                            .parse::<Header>()
+                            .unwrap(),
+                    ),
+                    Err(err) => Response::from_string(err.to_string()).with_status_code(500),
+                },
+                _ => Response::from_string("Not Found").with_status_code(404),
+            };
+
+            if let Err(err) = request.respond(response) {
+                warn!("Metrics: {}", err);
+            }
+        }
+    });
+
+    Ok(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Metrics, Outcome};
+    use crate::stage::Stage;
+
+    #[test]
+    fn encode_metrics() -> anyhow::Result<()> {
+        let metrics = Metrics::new()?;
+
+        metrics.observe_stage(&Stage::AddImages, Outcome::Fulfilled);
+        metrics.observe_stage(&Stage::AddImages, Outcome::Retry);
+        metrics.observe_uploaded(2048);
+        metrics.set_nozomi_page("index-korean", "korean", 3);
+
+        {
+            let _in_flight = metrics.in_flight();
+            assert!(metrics
+                .encode()?
+                .contains("madome_synchronizer_in_flight_ids 1"));
+        }
+
+        let text = metrics.encode()?;
+
+        assert!(text.contains(
+            r#"madome_synchronizer_stages_total{outcome="fulfilled",stage="add_images"} 1"#
+        ));
+        assert!(text
+            .contains(r#"madome_synchronizer_stages_total{outcome="retry",stage="add_images"} 1"#));
+        assert!(text.contains("madome_synchronizer_uploaded_bytes_count 1"));
+        assert!(text.contains(
+            r#"madome_synchronizer_nozomi_page{language="korean",source="index-korean"} 3"#
+        ));
+        assert!(text.contains("madome_synchronizer_in_flight_ids 0"));
+
+        Ok(())
+    }
+}
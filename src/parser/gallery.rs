@@ -1,13 +1,20 @@
+use std::sync::Arc;
+
 use anyhow;
 use log::trace;
 use madome_client::book::{Metadata, MetadataBook};
-use reqwest;
+use reqwest::Url;
 use scraper::{Html, Selector};
 
+use crate::error::SyncError;
+use crate::http::Request;
 use crate::parser::Parser;
+use crate::tag::{self, Tag};
+use crate::upstream::{self, Upstream};
 
 pub struct Gallery {
     id: u32,
+    upstream: Arc<Upstream>,
     request_data: Option<Box<String>>,
 }
 
@@ -33,83 +40,122 @@ impl Gallery {
     pub fn new(id: u32) -> Gallery {
         Gallery {
             id,
+            upstream: upstream::default_upstream(),
             request_data: None,
         }
     }
 
+    pub fn with_upstream(mut self, upstream: Arc<Upstream>) -> Gallery {
+        self.upstream = upstream;
+        self
+    }
+
+    fn layout_changed(&self, field: &str, selector: &str) -> SyncError {
+        SyncError::layout_changed(self.id, field, selector)
+    }
+
     pub fn is_nothing(&self, element: &scraper::ElementRef<'_>) -> bool {
-        element.text().next().unwrap().trim() == "N/A"
+        element.text().next().map(str::trim) == Some("N/A")
     }
 
-    pub fn parse_multiple_metadata(&self, element: scraper::ElementRef) -> Vec<String> {
+    pub fn parse_multiple_metadata(
+        &self,
+        field: &str,
+        element: scraper::ElementRef,
+    ) -> anyhow::Result<Vec<String>> {
         let ul_selector = Selector::parse("ul").unwrap();
         let li_selector = Selector::parse("li").unwrap();
 
-        element
+        let ul = element
             .select(&ul_selector)
             .next()
-            .unwrap()
-            .select(&li_selector)
-            .map(|element| element.text().next().unwrap().to_string())
-            .collect::<Vec<_>>()
+            .ok_or_else(|| self.layout_changed(field, "ul"))?;
+
+        ul.select(&li_selector)
+            .map(|element| {
+                element
+                    .text()
+                    .next()
+                    .map(|text| text.to_string())
+                    .ok_or_else(|| self.layout_changed(field, "ul > li").into())
+            })
+            .collect()
     }
 
-    pub fn parse_characters(&self, element: scraper::ElementRef) -> Option<Vec<String>> {
-        let characters = self.parse_multiple_metadata(element);
+    pub fn parse_characters(
+        &self,
+        element: scraper::ElementRef,
+    ) -> anyhow::Result<Option<Vec<String>>> {
+        let characters = self.parse_multiple_metadata("Characters", element)?;
 
         if characters.is_empty() {
-            return None;
+            return Ok(None);
         }
 
-        Some(characters)
+        Ok(Some(characters))
     }
 
-    pub fn parse_groups(&self, element: scraper::ElementRef) -> Option<Vec<String>> {
+    pub fn parse_groups(
+        &self,
+        element: scraper::ElementRef,
+    ) -> anyhow::Result<Option<Vec<String>>> {
         if self.is_nothing(&element) {
-            return None;
+            return Ok(None);
         }
 
-        let groups = self.parse_multiple_metadata(element);
+        let groups = self.parse_multiple_metadata("Group", element)?;
 
-        Some(groups)
+        Ok(Some(groups))
     }
 
-    pub fn parse_tags(&self, element: scraper::ElementRef) -> Option<Vec<String>> {
-        let tags = self.parse_multiple_metadata(element);
+    pub fn parse_tags(&self, element: scraper::ElementRef) -> anyhow::Result<Option<Vec<Tag>>> {
+        let tags = self
+            .parse_multiple_metadata("Tags", element)?
+            .iter()
+            .map(|tag| tag.parse().map_err(|_| self.layout_changed("Tags", "li")))
+            .collect::<Result<Vec<Tag>, _>>()?;
 
         if tags.is_empty() {
-            return None;
+            return Ok(None);
         }
 
-        Some(tags)
+        Ok(Some(tags))
     }
 
-    pub fn parse_metadata(&self, document: &Html, metadata_type: Metadata) -> Metadata {
+    pub fn parse_metadata(
+        &self,
+        document: &Html,
+        metadata_type: Metadata,
+    ) -> anyhow::Result<Metadata> {
         let gallery_info_selector = Selector::parse(".gallery-info > table").unwrap();
         let tr_selector = Selector::parse("tr").unwrap();
         let td_selector = Selector::parse("td").unwrap();
 
+        let field = metadata_type.as_str();
+
         let r = document
             .select(&gallery_info_selector)
             .next()
-            .unwrap()
+            .ok_or_else(|| self.layout_changed(field, ".gallery-info > table"))?
             .select(&tr_selector)
             .find(|element| {
-                let element = element.select(&td_selector).next().unwrap();
-
-                element.text().next().unwrap() == metadata_type.as_str()
+                element
+                    .select(&td_selector)
+                    .next()
+                    .and_then(|element| element.text().next())
+                    == Some(field)
             })
-            .unwrap()
-            .select(&td_selector)
-            .nth(1)
-            .unwrap();
-
-        match metadata_type {
-            Metadata::Characters(_) => Metadata::Characters(self.parse_characters(r)),
-            Metadata::Groups(_) => Metadata::Groups(self.parse_groups(r)),
-            Metadata::Tags(_) => Metadata::Tags(self.parse_tags(r)),
+            .and_then(|element| element.select(&td_selector).nth(1))
+            .ok_or_else(|| self.layout_changed(field, "tr > td"))?;
+
+        let r = match metadata_type {
+            Metadata::Characters(_) => Metadata::Characters(self.parse_characters(r)?),
+            Metadata::Groups(_) => Metadata::Groups(self.parse_groups(r)?),
+            Metadata::Tags(_) => Metadata::Tags(self.parse_tags(r)?.map(tag::to_madome)),
             _ => metadata_type,
-        }
+        };
+
+        Ok(r)
     }
 }
 
@@ -121,39 +167,54 @@ impl Parser for Gallery {
         trace!("Gallery::request_data()");
         match self.request_data {
             Some(ref rd) => Ok(rd),
-            None => Err(anyhow::Error::msg("Can't get request_data")),
+            None => Err(SyncError::NotRequested.into()),
         }
     }
 
     fn url(&self) -> anyhow::Result<String> {
         trace!("Gallery::url()");
-        let gallery_url = format!("https://hitomi.la/galleries/{}.html", self.id);
+        let gallery_url = self
+            .upstream
+            .endpoints
+            .hitomi(&format!("galleries/{}.html", self.id));
 
-        let client = reqwest::blocking::Client::builder().build()?;
+        let response = self.upstream.send(Request::get(&gallery_url))?;
+
+        if !response.is_success() {
+            return Err(SyncError::from_status(gallery_url, response.status).into());
+        }
 
-        let gallery_html = client.get(&gallery_url).send()?.text()?;
+        let gallery_html = response.text()?;
 
         let document = Html::parse_document(&gallery_html);
         let content_url_selector = Selector::parse("body > a").unwrap();
 
-        let anchor_element = document.select(&content_url_selector).next().unwrap();
+        let content_url = document
+            .select(&content_url_selector)
+            .next()
+            .and_then(|anchor_element| anchor_element.value().attr("href"))
+            .ok_or_else(|| self.layout_changed("ContentURL", "body > a[href]"))?;
 
-        let content_url = anchor_element
-            .value()
-            .attr("href")
-            .expect("Can't find `Content URL` in `parser::Gallery`")
-            .to_string();
+        // https://hitomi.la/doujinshi/...html => {hitomi}/doujinshi/...html
+        let content_path = match Url::parse(content_url) {
+            Ok(url) => url.path().to_string(),
+            Err(_) => content_url.to_string(),
+        };
 
-        Ok(content_url)
+        Ok(self.upstream.endpoints.hitomi(&content_path))
     }
 
     fn request(mut self) -> anyhow::Result<Box<Self>> {
         trace!("Gallery::request()");
         let content_url = self.url()?;
 
-        let client = reqwest::blocking::Client::builder().build()?;
+        let response = self.upstream.send(Request::get(&content_url))?;
+
+        if !response.is_success() {
+            return Err(SyncError::from_status(content_url, response.status).into());
+        }
 
-        let content_html = client.get(&content_url).send()?.text()?;
+        let content_html = response.text()?;
 
         self.request_data = Some(Box::new(content_html));
         Ok(Box::new(self))
@@ -166,8 +227,8 @@ impl Parser for Gallery {
         let document = Html::parse_document(&self.request_data()?);
 
         // let id = Metadata::ID(Some(self.id));
-        let characters = self.parse_metadata(&document, Metadata::Characters(None));
-        let groups = self.parse_metadata(&document, Metadata::Groups(None));
+        let characters = self.parse_metadata(&document, Metadata::Characters(None))?;
+        let groups = self.parse_metadata(&document, Metadata::Groups(None))?;
 
         let metadata_book = MetadataBook {
             characters,
@@ -195,16 +256,17 @@ mod tests {
 
     use super::Gallery;
     use super::Parser;
+    use crate::parser::test_upstream;
 
     #[test]
     fn parse_tags() -> anyhow::Result<()> {
-        let gallery = Gallery::new(1724122);
+        let gallery = Gallery::new(1724122).with_upstream(test_upstream());
 
         let gallery = gallery.request()?;
 
         let document = Html::parse_document(&gallery.request_data()?);
 
-        let tags = gallery.parse_metadata(&document, Metadata::Tags(None));
+        let tags = gallery.parse_metadata(&document, Metadata::Tags(None))?;
 
         let expected = Metadata::Tags(Some(
             ["footjob ♀", "loli ♀", "sister ♀", "incest"]
@@ -220,13 +282,13 @@ mod tests {
 
     #[test]
     fn parse_tags_is_nothing() -> anyhow::Result<()> {
-        let gallery = Gallery::new(1752881);
+        let gallery = Gallery::new(1752881).with_upstream(test_upstream());
 
         let gallery = gallery.request()?;
 
         let document = Html::parse_document(&gallery.request_data()?);
 
-        let tags = gallery.parse_metadata(&document, Metadata::Tags(None));
+        let tags = gallery.parse_metadata(&document, Metadata::Tags(None))?;
 
         let expected = Metadata::Tags(None);
 
@@ -237,13 +299,13 @@ mod tests {
 
     #[test]
     fn parse_characters() -> anyhow::Result<()> {
-        let gallery = Gallery::new(1277807);
+        let gallery = Gallery::new(1277807).with_upstream(test_upstream());
 
         let gallery = gallery.request()?;
 
         let document = Html::parse_document(&gallery.request_data()?);
 
-        let characters = gallery.parse_metadata(&document, Metadata::Characters(None));
+        let characters = gallery.parse_metadata(&document, Metadata::Characters(None))?;
 
         let expected = Metadata::Characters(Some(
             [
@@ -271,13 +333,13 @@ mod tests {
 
     #[test]
     fn parse_characters_is_nothing() -> anyhow::Result<()> {
-        let gallery = Gallery::new(1745756);
+        let gallery = Gallery::new(1745756).with_upstream(test_upstream());
 
         let gallery = gallery.request()?;
 
         let document = Html::parse_document(&gallery.request_data()?);
 
-        let characters = gallery.parse_metadata(&document, Metadata::Characters(None));
+        let characters = gallery.parse_metadata(&document, Metadata::Characters(None))?;
 
         let expected = Metadata::Characters(None);
 
@@ -288,13 +350,13 @@ mod tests {
 
     #[test]
     fn parse_groups() -> anyhow::Result<()> {
-        let gallery = Gallery::new(1705277);
+        let gallery = Gallery::new(1705277).with_upstream(test_upstream());
 
         let gallery = gallery.request()?;
 
         let document = Html::parse_document(&gallery.request_data()?);
 
-        let groups = gallery.parse_metadata(&document, Metadata::Groups(None));
+        let groups = gallery.parse_metadata(&document, Metadata::Groups(None))?;
 
         let expected = Metadata::Groups(Some(vec!["haniya".to_string()]));
 
@@ -305,13 +367,13 @@ mod tests {
 
     #[test]
     fn parse_groups_is_nothing() -> anyhow::Result<()> {
-        let gallery = Gallery::new(1454325);
+        let gallery = Gallery::new(1454325).with_upstream(test_upstream());
 
         let gallery = gallery.request()?;
 
         let document = Html::parse_document(&gallery.request_data()?);
 
-        let groups = gallery.parse_metadata(&document, Metadata::Groups(None));
+        let groups = gallery.parse_metadata(&document, Metadata::Groups(None))?;
 
         let expected = Metadata::Groups(None);
 
@@ -1,14 +1,21 @@
+use std::sync::Arc;
+
 use anyhow;
 use log::trace;
 use madome_client::book::{ContentType, Language, Metadata, MetadataBook};
-use reqwest;
 use scraper::{Html, Selector};
 
+use crate::error::SyncError;
+use crate::http::Request;
 use crate::parser::Parser;
+use crate::tag::{self, Tag};
+use crate::upstream::{self, Upstream};
+use crate::utils::parse_date;
 
 /// Can't parse Groups, Characters
 pub struct GalleryBlock {
     id: u32,
+    upstream: Arc<Upstream>,
     request_data: Option<Box<String>>,
 }
 
@@ -16,84 +23,105 @@ impl GalleryBlock {
     pub fn new(id: u32) -> GalleryBlock {
         GalleryBlock {
             id,
+            upstream: upstream::default_upstream(),
             request_data: None,
         }
     }
 
-    pub fn parse_single_metadata(&self, element: scraper::ElementRef) -> String {
+    pub fn with_upstream(mut self, upstream: Arc<Upstream>) -> GalleryBlock {
+        self.upstream = upstream;
+        self
+    }
+
+    fn layout_changed(&self, field: &str, selector: &str) -> SyncError {
+        SyncError::layout_changed(self.id, field, selector)
+    }
+
+    pub fn parse_single_metadata(
+        &self,
+        field: &str,
+        element: scraper::ElementRef,
+    ) -> anyhow::Result<String> {
         let anchor_selector = Selector::parse("a").unwrap();
 
-        element
+        let text = element
             .select(&anchor_selector)
             .next()
-            .unwrap()
-            .text()
-            .next()
-            .unwrap()
-            .to_string()
+            .and_then(|anchor| anchor.text().next())
+            .ok_or_else(|| self.layout_changed(field, "a"))?;
+
+        Ok(text.to_string())
     }
 
-    pub fn parse_multiple_metadata(&self, element: scraper::ElementRef) -> Vec<String> {
+    pub fn parse_multiple_metadata(
+        &self,
+        field: &str,
+        element: scraper::ElementRef,
+    ) -> anyhow::Result<Vec<String>> {
         let ul_selector = Selector::parse("ul").unwrap();
         let li_selector = Selector::parse("li").unwrap();
 
-        element
+        let ul = element
             .select(&ul_selector)
             .next()
-            .unwrap()
-            .select(&li_selector)
-            .map(|element| element.text().next().unwrap().to_string())
-            .collect::<Vec<_>>()
+            .ok_or_else(|| self.layout_changed(field, "ul"))?;
+
+        ul.select(&li_selector)
+            .map(|element| {
+                element
+                    .text()
+                    .next()
+                    .map(|text| text.to_string())
+                    .ok_or_else(|| self.layout_changed(field, "ul > li").into())
+            })
+            .collect()
     }
 
-    pub fn parse_title(&self, fragment: &Html) -> String {
+    pub fn parse_title(&self, fragment: &Html) -> anyhow::Result<String> {
         let title_selector = Selector::parse("h1.lillie > a").unwrap();
 
-        fragment
+        let title = fragment
             .select(&title_selector)
             .next()
-            .unwrap()
-            .text()
-            .next()
-            .unwrap()
-            .to_string()
+            .and_then(|element| element.text().next())
+            .ok_or_else(|| self.layout_changed("Title", "h1.lillie > a"))?;
+
+        Ok(title.to_string())
     }
 
     pub fn is_nothing(&self, element: &scraper::ElementRef<'_>) -> bool {
-        element.text().next().unwrap().trim() == "N/A"
+        element.text().next().map(str::trim) == Some("N/A")
     }
 
     /// Change return type to Option<Vec<String>>
     /// and check N/A
-    pub fn parse_artists(&self, fragment: &Html) -> Option<Vec<String>> {
+    pub fn parse_artists(&self, fragment: &Html) -> anyhow::Result<Option<Vec<String>>> {
         let artist_list_selector = Selector::parse(".artist-list").unwrap();
-        let ul_selector = Selector::parse("ul").unwrap();
-        let li_selector = Selector::parse("li").unwrap();
 
-        let artist_list = fragment.select(&artist_list_selector).next().unwrap();
+        let artist_list = fragment
+            .select(&artist_list_selector)
+            .next()
+            .ok_or_else(|| self.layout_changed("Artists", ".artist-list"))?;
 
         if self.is_nothing(&artist_list) {
-            return None;
+            return Ok(None);
         }
 
-        let ul = artist_list.select(&ul_selector).next().unwrap();
-
-        Some(
-            ul.select(&li_selector)
-                .map(|element| element.text().next().unwrap().to_string())
-                .collect::<Vec<_>>(),
-        )
+        Ok(Some(self.parse_multiple_metadata("Artists", artist_list)?))
     }
 
-    pub fn parse_series(&self, element: scraper::ElementRef) -> Option<Vec<String>> {
+    pub fn parse_series(
+        &self,
+        element: scraper::ElementRef,
+    ) -> anyhow::Result<Option<Vec<String>>> {
         if self.is_nothing(&element) {
-            return None;
+            return Ok(None);
         }
 
-        Some(self.parse_multiple_metadata(element))
+        Ok(Some(self.parse_multiple_metadata("Series", element)?))
     }
 
-    pub fn parse_tags(&self, element: scraper::ElementRef) -> Option<Vec<String>> {
+    pub fn parse_tags(&self, element: scraper::ElementRef) -> anyhow::Result<Option<Vec<Tag>>> {
         // <tr>
         // <td>Tags</td>
         // <td class="relatedtags">
@@ -105,138 +133,167 @@ impl GalleryBlock {
             return None;
         } */
 
-        let tags = self.parse_multiple_metadata(element);
+        let tags = self
+            .parse_multiple_metadata("Tags", element)?
+            .iter()
+            .map(|tag| tag.parse().map_err(|_| self.layout_changed("Tags", "li")))
+            .collect::<Result<Vec<Tag>, _>>()?;
 
         if tags.is_empty() {
-            return None;
+            return Ok(None);
         }
 
-        Some(tags)
+        Ok(Some(tags))
     }
 
-    pub fn parse_content_type(&self, element: scraper::ElementRef) -> Option<ContentType> {
+    pub fn parse_content_type(
+        &self,
+        element: scraper::ElementRef,
+    ) -> anyhow::Result<Option<ContentType>> {
         if self.is_nothing(&element) {
-            return None;
+            return Ok(None);
         }
 
-        Some(ContentType::from(self.parse_single_metadata(element)))
+        let content_type = self.parse_single_metadata("Type", element)?;
+
+        Ok(Some(ContentType::from(content_type)))
     }
 
-    pub fn parse_language(&self, element: scraper::ElementRef) -> Option<Language> {
+    pub fn parse_language(&self, element: scraper::ElementRef) -> anyhow::Result<Option<Language>> {
         if self.is_nothing(&element) {
-            return None;
+            return Ok(None);
         }
 
-        Some(Language::from(self.parse_single_metadata(element).as_str()))
+        let language = self.parse_single_metadata("Language", element)?;
+
+        Ok(Some(Language::from(language.as_str())))
     }
 
-    /// uncomment on v2 api
-    pub fn parse_created_at(&self, fragment: &Html) -> Option<String> {
+    /// UTC ISO-8601, `2020-09-02T15:01:00Z`
+    pub fn parse_created_at(&self, fragment: &Html) -> anyhow::Result<Option<String>> {
         let date_selector = Selector::parse(".date").unwrap();
 
         let date_str = fragment
             .select(&date_selector)
             .next()
-            .unwrap()
-            .text()
-            .next()
-            .unwrap()
+            .and_then(|element| element.text().next())
+            .ok_or_else(|| self.layout_changed("CreatedAt", ".date"))?
             .trim();
 
-        /* let c = &date_str[..date_str.len() - 3];
-
-        let cc = &date_str[c.len()..];
+        let date = parse_date(date_str).ok_or_else(|| SyncError::InvalidDate {
+            id: self.id,
+            date: date_str.to_string(),
+        })?;
 
-        let mut date_string = String::new();
-
-        date_string.push_str(c);
-        date_string.push(' ');
-        date_string.push_str(cc);
-        date_string.push_str(":00"); */
-
-        Some(date_str.to_string())
+        Ok(Some(date))
     }
 
-    pub fn parse_thumbnail_url(&self, fragment: &Html) -> String {
+    /// Path of bigtn without host
+    pub fn parse_thumbnail_url(&self, fragment: &Html) -> anyhow::Result<String> {
         let anchor_selector = Selector::parse("a").unwrap();
         let img_selector = Selector::parse("img").unwrap();
 
-        let anchor = fragment.select(&anchor_selector).next().unwrap();
-
-        anchor
-            .select(&img_selector)
+        let src = fragment
+            .select(&anchor_selector)
             .next()
-            .unwrap()
-            .value()
-            .attr("src")
-            .unwrap()[("//tn.hitomi.la").len()..]
-            .replace("smallbig", "big")
+            .and_then(|anchor| anchor.select(&img_selector).next())
+            .and_then(|img| img.value().attr("src"))
+            .ok_or_else(|| self.layout_changed("ThumbnailURL", "a img[src]"))?;
+
+        // //tn.hitomi.la/smallbigtn/... => /smallbigtn/...
+        let path = match src.find("//") {
+            Some(i) => {
+                let host_and_path = &src[i + 2..];
+                &host_and_path[host_and_path.find('/').unwrap_or(host_and_path.len())..]
+            }
+            None => src,
+        };
+
+        Ok(path.replace("smallbig", "big"))
     }
 
     #[deprecated]
-    pub fn parse_content_url(&self, fragment: &Html) -> String {
+    pub fn parse_content_url(&self, fragment: &Html) -> anyhow::Result<String> {
         let anchor_selector = Selector::parse("a").unwrap();
 
-        fragment
+        let content_url = fragment
             .select(&anchor_selector)
             .next()
-            .unwrap()
-            .value()
-            .attr("href")
-            .unwrap()
-            .to_string()
-    }
-
-    pub fn parse_metadata(&self, fragment: &Html, metadata_type: Metadata) -> Metadata {
-        match metadata_type {
-            Metadata::Title(_) => Metadata::Title(Some(self.parse_title(fragment))),
-            Metadata::Artists(_) => Metadata::Artists(self.parse_artists(fragment)),
-            Metadata::CreatedAt(_) => Metadata::CreatedAt(self.parse_created_at(fragment)),
+            .and_then(|anchor| anchor.value().attr("href"))
+            .ok_or_else(|| self.layout_changed("ContentURL", "a[href]"))?;
+
+        Ok(content_url.to_string())
+    }
+
+    /// `<td>` of the value of `metadata_type` in `.dj-desc` table
+    fn metadata_td<'a>(
+        &self,
+        fragment: &'a Html,
+        metadata_type: &Metadata,
+    ) -> anyhow::Result<scraper::ElementRef<'a>> {
+        let metadata_table_selector = Selector::parse(".dj-content > .dj-desc").unwrap();
+        let tr_element_selector = Selector::parse("tr").unwrap();
+        let td_element_selector = Selector::parse("td").unwrap();
+
+        let field = metadata_type.as_str();
+
+        let td = fragment
+            .select(&metadata_table_selector)
+            .next()
+            .ok_or_else(|| self.layout_changed(field, ".dj-content > .dj-desc"))?
+            .select(&tr_element_selector)
+            .find(|element| {
+                element
+                    .select(&td_element_selector)
+                    .next()
+                    .and_then(|element| element.text().next())
+                    == Some(field)
+            })
+            /*
+            <tr>
+                <td>Series</td>
+                <td>
+                    N/A
+                </td>
+            </tr>
+            */
+            .and_then(|element| element.select(&td_element_selector).nth(1))
+            /*
+            0: <td>Series</td>
+            1: <td>N/A</td>
+            */
+            .ok_or_else(|| self.layout_changed(field, "tr > td"))?;
+
+        Ok(td)
+    }
+
+    pub fn parse_metadata(
+        &self,
+        fragment: &Html,
+        metadata_type: Metadata,
+    ) -> anyhow::Result<Metadata> {
+        let r = match metadata_type {
+            Metadata::Title(_) => Metadata::Title(Some(self.parse_title(fragment)?)),
+            Metadata::Artists(_) => Metadata::Artists(self.parse_artists(fragment)?),
+            Metadata::CreatedAt(_) => Metadata::CreatedAt(self.parse_created_at(fragment)?),
             //  Metadata::ContentURL(_) => Metadata::ContentURL(Some(self.parse_content_url(fragment))),
             Metadata::ThumbnailURL(_) => {
-                Metadata::ThumbnailURL(Some(self.parse_thumbnail_url(fragment)))
+                Metadata::ThumbnailURL(Some(self.parse_thumbnail_url(fragment)?))
             }
             _ => {
-                let metadata_table_selector = Selector::parse(".dj-content > .dj-desc").unwrap();
-                let tr_element_selector = Selector::parse("tr").unwrap();
-                let td_element_selector = Selector::parse("td").unwrap();
-
-                let r = fragment
-                    .select(&metadata_table_selector)
-                    .next()
-                    .unwrap()
-                    .select(&tr_element_selector)
-                    .find(|element| {
-                        let element = element.select(&td_element_selector).next().unwrap();
-
-                        element.text().next().unwrap() == metadata_type.as_str()
-                    })
-                    .unwrap()
-                    /*
-                    <tr>
-                        <td>Series</td>
-                        <td>
-                            N/A
-                        </td>
-                    </tr>
-                    */
-                    .select(&td_element_selector)
-                    /*
-                    0: <td>Series</td>
-                    1: <td>N/A</td>
-                    */
-                    .nth(1)
-                    .unwrap();
+                let r = self.metadata_td(fragment, &metadata_type)?;
 
                 match metadata_type {
-                    Metadata::ContentType(_) => Metadata::ContentType(self.parse_content_type(r)),
-                    Metadata::Language(_) => Metadata::Language(self.parse_language(r)),
-                    Metadata::Series(_) => Metadata::Series(self.parse_series(r)),
-                    Metadata::Tags(_) => Metadata::Tags(self.parse_tags(r)),
+                    Metadata::ContentType(_) => Metadata::ContentType(self.parse_content_type(r)?),
+                    Metadata::Language(_) => Metadata::Language(self.parse_language(r)?),
+                    Metadata::Series(_) => Metadata::Series(self.parse_series(r)?),
+                    Metadata::Tags(_) => Metadata::Tags(self.parse_tags(r)?.map(tag::to_madome)),
                     _ => metadata_type,
                 }
             }
-        }
+        };
+
+        Ok(r)
     }
 }
 
@@ -248,23 +305,28 @@ impl Parser for GalleryBlock {
         trace!("GalleryBlock::request_data()");
         match self.request_data {
             Some(ref rd) => Ok(rd),
-            None => Err(anyhow::Error::msg("Can't get request_data")),
+            None => Err(SyncError::NotRequested.into()),
         }
     }
 
     fn url(&self) -> anyhow::Result<String> {
         trace!("GalleryBlock::url()");
-        Ok(format!(
-            "https://ltn.hitomi.la/galleryblock/{}.html",
-            self.id
-        ))
+        Ok(self
+            .upstream
+            .endpoints
+            .ltn(&format!("galleryblock/{}.html", self.id)))
     }
 
     fn request(mut self) -> anyhow::Result<Box<Self>> {
         trace!("GalleryBlock::request()");
-        let client = reqwest::blocking::Client::builder().build()?;
+        let url = self.url()?;
+        let response = self.upstream.send(Request::get(&url))?;
+
+        if !response.is_success() {
+            return Err(SyncError::from_status(url, response.status).into());
+        }
 
-        let gallery_block_html = client.get(&self.url()?).send()?.text()?;
+        let gallery_block_html = response.text()?;
 
         self.request_data = Some(Box::new(gallery_block_html));
 
@@ -276,14 +338,14 @@ impl Parser for GalleryBlock {
         let fragment = Html::parse_fragment(&self.request_data()?);
 
         let id = Metadata::ID(Some(self.id));
-        let title = self.parse_metadata(&fragment, Metadata::Title(None));
-        let artists = self.parse_metadata(&fragment, Metadata::Artists(None));
-        let series = self.parse_metadata(&fragment, Metadata::Series(None));
-        let tags = self.parse_metadata(&fragment, Metadata::Tags(None));
-        let language = self.parse_metadata(&fragment, Metadata::Language(None));
-        let content_type = self.parse_metadata(&fragment, Metadata::ContentType(None));
-        let created_at = self.parse_metadata(&fragment, Metadata::CreatedAt(None));
-        let thumbnail_url = self.parse_metadata(&fragment, Metadata::ThumbnailURL(None));
+        let title = self.parse_metadata(&fragment, Metadata::Title(None))?;
+        let artists = self.parse_metadata(&fragment, Metadata::Artists(None))?;
+        let series = self.parse_metadata(&fragment, Metadata::Series(None))?;
+        let tags = self.parse_metadata(&fragment, Metadata::Tags(None))?;
+        let language = self.parse_metadata(&fragment, Metadata::Language(None))?;
+        let content_type = self.parse_metadata(&fragment, Metadata::ContentType(None))?;
+        let created_at = self.parse_metadata(&fragment, Metadata::CreatedAt(None))?;
+        let thumbnail_url = self.parse_metadata(&fragment, Metadata::ThumbnailURL(None))?;
         // let content_url = self.parse_metadata(&fragment, Metadata::ContentURL(None));
 
         let metadata_book = MetadataBook {
@@ -314,10 +376,12 @@ mod tests {
     use super::Language;
     use super::Metadata;
     use super::Parser;
+    use crate::error::SyncError;
+    use crate::parser::test_upstream;
 
     /* #[test]
      fn parse_gallery_block() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1724122);
+        let gallery_block = GalleryBlock::new(1724122).with_upstream(test_upstream());
 
         let rd = gallery_block.request()?;
 
@@ -360,13 +424,13 @@ mod tests {
 
     #[test]
     fn parse_title() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1399900);
+        let gallery_block = GalleryBlock::new(1399900).with_upstream(test_upstream());
 
         let gallery_block = gallery_block.request()?;
 
         let fragment = Html::parse_fragment(&gallery_block.request_data()?);
 
-        let title = gallery_block.parse_metadata(&fragment, Metadata::Title(None));
+        let title = gallery_block.parse_metadata(&fragment, Metadata::Title(None))?;
 
         let expected = Metadata::Title(Some("COMIC LO 2019-05".to_string()));
 
@@ -377,7 +441,7 @@ mod tests {
 
     /* #[test]
      fn parse_content_url() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1399900);
+        let gallery_block = GalleryBlock::new(1399900).with_upstream(test_upstream());
 
         let gallery_block = gallery_block.request()?;
 
@@ -396,13 +460,13 @@ mod tests {
 
     #[test]
     fn parse_thumbnail_url() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1399900);
+        let gallery_block = GalleryBlock::new(1399900).with_upstream(test_upstream());
 
         let gallery_block = gallery_block.request()?;
 
         let fragment = Html::parse_fragment(&gallery_block.request_data()?);
 
-        let content_url = gallery_block.parse_metadata(&fragment, Metadata::ThumbnailURL(None));
+        let content_url = gallery_block.parse_metadata(&fragment, Metadata::ThumbnailURL(None))?;
 
         let expected = Metadata::ThumbnailURL(Some(
             "/bigtn/2/7b/63c1f20d7bb770faadf60a1a353d64f29c0d51f958bca76cc8e05fb3d19f57b2.jpg"
@@ -416,13 +480,13 @@ mod tests {
 
     #[test]
     fn parse_artists() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1399900);
+        let gallery_block = GalleryBlock::new(1399900).with_upstream(test_upstream());
 
         let gallery_block = gallery_block.request()?;
 
         let fragment = Html::parse_fragment(&gallery_block.request_data()?);
 
-        let artists = gallery_block.parse_metadata(&fragment, Metadata::Artists(None));
+        let artists = gallery_block.parse_metadata(&fragment, Metadata::Artists(None))?;
 
         let expected = Metadata::Artists(Some(
             [
@@ -457,13 +521,13 @@ mod tests {
 
     #[test]
     fn parse_artists_is_nothing() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1722267);
+        let gallery_block = GalleryBlock::new(1722267).with_upstream(test_upstream());
 
         let gallery_block = gallery_block.request()?;
 
         let fragment = Html::parse_fragment(&gallery_block.request_data()?);
 
-        let artists = gallery_block.parse_metadata(&fragment, Metadata::Artists(None));
+        let artists = gallery_block.parse_metadata(&fragment, Metadata::Artists(None))?;
 
         let expected = Metadata::Artists(None);
 
@@ -474,13 +538,13 @@ mod tests {
 
     #[test]
     fn parse_language() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1399900);
+        let gallery_block = GalleryBlock::new(1399900).with_upstream(test_upstream());
 
         let gallery_block = gallery_block.request()?;
 
         let fragment = Html::parse_fragment(&gallery_block.request_data()?);
 
-        let content_type = gallery_block.parse_metadata(&fragment, Metadata::Language(None));
+        let content_type = gallery_block.parse_metadata(&fragment, Metadata::Language(None))?;
 
         let expected = Metadata::Language(Some(Language::Korean));
 
@@ -491,13 +555,13 @@ mod tests {
 
     #[test]
     fn parse_content_type() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1399900);
+        let gallery_block = GalleryBlock::new(1399900).with_upstream(test_upstream());
 
         let gallery_block = gallery_block.request()?;
 
         let fragment = Html::parse_fragment(&gallery_block.request_data()?);
 
-        let content_type = gallery_block.parse_metadata(&fragment, Metadata::ContentType(None));
+        let content_type = gallery_block.parse_metadata(&fragment, Metadata::ContentType(None))?;
 
         let expected = Metadata::ContentType(Some(ContentType::Manga));
 
@@ -508,13 +572,13 @@ mod tests {
 
     #[test]
     fn parse_series() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1277807);
+        let gallery_block = GalleryBlock::new(1277807).with_upstream(test_upstream());
 
         let gallery_block = gallery_block.request()?;
 
         let fragment = Html::parse_fragment(&gallery_block.request_data()?);
 
-        let series = gallery_block.parse_metadata(&fragment, Metadata::Series(None));
+        let series = gallery_block.parse_metadata(&fragment, Metadata::Series(None))?;
 
         let e = [
             "eromanga sensei",
@@ -541,13 +605,13 @@ mod tests {
 
     #[test]
     fn parse_series_is_nothing() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1399900);
+        let gallery_block = GalleryBlock::new(1399900).with_upstream(test_upstream());
 
         let gallery_block = gallery_block.request()?;
 
         let fragment = Html::parse_fragment(&gallery_block.request_data()?);
 
-        let series_nothing = gallery_block.parse_metadata(&fragment, Metadata::Series(None));
+        let series_nothing = gallery_block.parse_metadata(&fragment, Metadata::Series(None))?;
 
         let expected = Metadata::Series(None);
 
@@ -558,13 +622,13 @@ mod tests {
 
     #[test]
     fn parse_tags() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1724122);
+        let gallery_block = GalleryBlock::new(1724122).with_upstream(test_upstream());
 
         let gallery_block = gallery_block.request()?;
 
         let fragment = Html::parse_fragment(&gallery_block.request_data()?);
 
-        let tags = gallery_block.parse_metadata(&fragment, Metadata::Tags(None));
+        let tags = gallery_block.parse_metadata(&fragment, Metadata::Tags(None))?;
 
         let expected = Metadata::Tags(Some(
             ["footjob ♀", "loli ♀", "sister ♀", "incest"]
@@ -580,13 +644,13 @@ mod tests {
 
     #[test]
     fn parse_tags_is_nothing() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1686905);
+        let gallery_block = GalleryBlock::new(1686905).with_upstream(test_upstream());
 
         let gallery_block = gallery_block.request()?;
 
         let fragment = Html::parse_fragment(&gallery_block.request_data()?);
 
-        let tags = gallery_block.parse_metadata(&fragment, Metadata::Tags(None));
+        let tags = gallery_block.parse_metadata(&fragment, Metadata::Tags(None))?;
 
         let expected = Metadata::Tags(None);
 
@@ -597,21 +661,40 @@ mod tests {
 
     #[test]
     fn parse_created_at() -> anyhow::Result<()> {
-        let gallery_block = GalleryBlock::new(1724122);
+        let gallery_block = GalleryBlock::new(1724122).with_upstream(test_upstream());
 
         let gallery_block = gallery_block.request()?;
 
         let fragment = Html::parse_fragment(&gallery_block.request_data()?);
 
-        let created_at = gallery_block.parse_metadata(&fragment, Metadata::CreatedAt(None));
-
-        // uncomment v2
-        // let expected = Metadata::CreatedAt(Some("2020-09-02 10:01:00 -05:00".to_string()));
+        let created_at = gallery_block.parse_metadata(&fragment, Metadata::CreatedAt(None))?;
 
-        let expected = Metadata::CreatedAt(Some("2020-09-02 10:01:00-05".to_string()));
+        // 2020-09-02 10:01:00-05
+        let expected = Metadata::CreatedAt(Some("2020-09-02T15:01:00Z".to_string()));
 
         assert_eq!(expected, created_at);
 
         Ok(())
     }
+
+    #[test]
+    fn layout_changed() {
+        let mut gallery_block = GalleryBlock::new(1724122);
+        gallery_block.request_data = Some(Box::new("<div>redesigned</div>".to_string()));
+
+        let err = gallery_block.parse().unwrap_err();
+
+        match err.downcast_ref::<SyncError>() {
+            Some(SyncError::ParseLayoutChanged {
+                id,
+                field,
+                selector,
+            }) => {
+                assert_eq!(1724122, *id);
+                assert_eq!("Title", field);
+                assert_eq!("h1.lillie > a", selector);
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
 }
@@ -0,0 +1,463 @@
+use std::sync::Arc;
+
+use anyhow;
+use log::trace;
+use madome_client::book::{ContentType, Language, Metadata, MetadataBook};
+use serde::Deserialize;
+use serde_json::{self, Value};
+
+use crate::error::SyncError;
+use crate::http::Request;
+use crate::parser::{File, Parser};
+use crate::tag::{self, Tag};
+use crate::upstream::{self, Upstream};
+use crate::utils::parse_date;
+
+/// # Gallery Info
+/// Every metadata of a book from `galleries/{id}.js`, instead of `GalleryBlock` and `Gallery`
+///
+/// ```js
+/// var galleryinfo = {"title":"...","type":"manga","language_localname":"한국어","date":"2020-09-02 10:01:00-05",
+/// "files":[...],"artists":[{"artist":"airandou"}],"groups":null,"parodys":null,"characters":null,
+/// "tags":[{"tag":"sole female","female":"1","male":""}]}
+/// ```
+pub struct GalleryInfo {
+    id: u32,
+    upstream: Arc<Upstream>,
+    request_data: Option<Box<String>>,
+    /// `request_data` parsed once, missing fields are `None` but fields of changed types are layout changes
+    data: Option<Value>,
+}
+
+/// Fields of `files`, separated from the metadata
+#[derive(Deserialize, Debug)]
+struct Files {
+    files: Vec<File>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TagInfo {
+    tag: String,
+    /// `"1"`, `1` or `""`
+    #[serde(default)]
+    female: Value,
+    #[serde(default)]
+    male: Value,
+}
+
+impl From<&TagInfo> for Tag {
+    fn from(x: &TagInfo) -> Self {
+        Tag::from_flags(x.tag.as_str(), is_flag(&x.female), is_flag(&x.male))
+    }
+}
+
+fn is_flag(x: &Value) -> bool {
+    match x {
+        Value::String(x) => x == "1",
+        Value::Number(x) => x.as_u64() == Some(1),
+        Value::Bool(x) => *x,
+        _ => false,
+    }
+}
+
+/// `[{"artist":"airandou"}]` => `["airandou"]`, `None` if missing or null
+fn names(id: u32, data: &Value, field: &str, key: &str) -> anyhow::Result<Option<Vec<String>>> {
+    let changed =
+        || SyncError::layout_changed(id, field, format!("galleryinfo.{}[].{}", field, key));
+
+    let names = match data.get(field) {
+        None | Some(Value::Null) => return Ok(None),
+        Some(names) => names.as_array().ok_or_else(changed)?,
+    };
+
+    let names = names
+        .iter()
+        .map(|x| {
+            x.get(key)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(changed)
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Some(names))
+}
+
+/// `None` if empty, same as `GalleryBlock`
+fn non_empty(names: Vec<String>) -> Option<Vec<String>> {
+    if names.is_empty() {
+        None
+    } else {
+        Some(names)
+    }
+}
+
+/// Response of `galleries/{id}.js` without `var galleryinfo =`
+pub(crate) fn request_galleryinfo(upstream: &Upstream, id: u32) -> anyhow::Result<String> {
+    let url = upstream.endpoints.ltn(&format!("galleries/{}.js", id));
+    let response = upstream.send(Request::get(&url))?;
+
+    if !response.is_success() {
+        return Err(SyncError::from_status(url, response.status).into());
+    }
+
+    let rd = response.text()?;
+
+    // var galleryinfo = {...}
+    let i = rd
+        .find('=')
+        .ok_or_else(|| SyncError::layout_changed(id, "galleryinfo", "var galleryinfo ="))?;
+
+    Ok(rd[i + 1..].to_string())
+}
+
+impl GalleryInfo {
+    pub fn new(id: u32) -> GalleryInfo {
+        GalleryInfo {
+            id,
+            upstream: upstream::default_upstream(),
+            request_data: None,
+            data: None,
+        }
+    }
+
+    pub fn with_upstream(mut self, upstream: Arc<Upstream>) -> GalleryInfo {
+        self.upstream = upstream;
+        self
+    }
+
+    fn set_request_data(&mut self, rd: String) -> anyhow::Result<()> {
+        let data = serde_json::from_str::<Value>(&rd).map_err(|err| {
+            SyncError::layout_changed(self.id, "galleryinfo", format!("json: {}", err))
+        })?;
+
+        self.request_data = Some(Box::new(rd));
+        self.data = Some(data);
+
+        Ok(())
+    }
+
+    fn data(&self) -> anyhow::Result<&Value> {
+        self.data
+            .as_ref()
+            .ok_or_else(|| SyncError::NotRequested.into())
+    }
+
+    /// String field, `None` if missing or null
+    fn str_field(&self, field: &str) -> anyhow::Result<Option<String>> {
+        match self.data()?.get(field) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(x)) => Ok(Some(x.to_string())),
+            Some(_) => {
+                Err(
+                    SyncError::layout_changed(self.id, field, format!("galleryinfo.{}", field))
+                        .into(),
+                )
+            }
+        }
+    }
+
+    /// Empty if missing or null
+    pub fn tags(&self) -> anyhow::Result<Vec<Tag>> {
+        let changed = || SyncError::layout_changed(self.id, "tags", "galleryinfo.tags[].tag");
+
+        let tags = match self.data()?.get("tags") {
+            None | Some(Value::Null) => return Ok(vec![]),
+            Some(tags) => tags.as_array().ok_or_else(changed)?,
+        };
+
+        tags.iter()
+            .map(|x| {
+                TagInfo::deserialize(x)
+                    .map(|x| Tag::from(&x))
+                    .map_err(|_| changed().into())
+            })
+            .collect()
+    }
+
+    /// `language` such as `korean`, same as `Image::language()`
+    pub fn language(&self) -> anyhow::Result<Option<String>> {
+        self.str_field("language")
+    }
+
+    /// Same as `Image::parse()` without another request, independent of the metadata fields
+    pub fn files(&self) -> anyhow::Result<Vec<File>> {
+        let files = Files::deserialize(self.data()?).map_err(|err| {
+            SyncError::layout_changed(self.id, "files", format!("galleryinfo.files: {}", err))
+        })?;
+
+        Ok(files.files)
+    }
+
+    /// Path of bigtn of the first file, same as `GalleryBlock`
+    fn thumbnail_url(&self, files: &[File]) -> anyhow::Result<String> {
+        let hash = files
+            .first()
+            .map(|file| file.hash.as_str())
+            .filter(|hash| hash.len() >= 3)
+            .ok_or_else(|| SyncError::layout_changed(self.id, "ThumbnailURL", "files[0].hash"))?;
+
+        let postfix = &hash[hash.len() - 3..];
+
+        Ok(format!(
+            "/bigtn/{}/{}/{}.jpg",
+            &postfix[2..],
+            &postfix[..2],
+            hash
+        ))
+    }
+}
+
+impl Parser for GalleryInfo {
+    type RequestData = String;
+    type ParseData = MetadataBook;
+
+    fn request_data(&self) -> anyhow::Result<&Box<Self::RequestData>> {
+        trace!("GalleryInfo::request_data()");
+        match self.request_data {
+            Some(ref rd) => Ok(rd),
+            None => Err(SyncError::NotRequested.into()),
+        }
+    }
+
+    fn url(&self) -> anyhow::Result<String> {
+        trace!("GalleryInfo::url()");
+        Ok(self
+            .upstream
+            .endpoints
+            .ltn(&format!("galleries/{}.js", self.id)))
+    }
+
+    fn request(mut self) -> anyhow::Result<Box<Self>> {
+        trace!("GalleryInfo::request()");
+        let rd = request_galleryinfo(&self.upstream, self.id)?;
+
+        self.set_request_data(rd)?;
+        Ok(Box::new(self))
+    }
+
+    fn parse(&self) -> anyhow::Result<Self::ParseData> {
+        trace!("GalleryInfo::parse()");
+        let data = self.data()?;
+        let files = self.files()?;
+
+        let title = self
+            .str_field("title")?
+            .filter(|title| !title.is_empty())
+            .ok_or_else(|| SyncError::layout_changed(self.id, "Title", "galleryinfo.title"))?;
+
+        let created_at = match self.str_field("date")? {
+            Some(date) => {
+                Some(parse_date(&date).ok_or(SyncError::InvalidDate { id: self.id, date })?)
+            }
+            None => None,
+        };
+
+        let names = |field: &str, key: &str| -> anyhow::Result<Option<Vec<String>>> {
+            Ok(non_empty(
+                names(self.id, data, field, key)?.unwrap_or_default(),
+            ))
+        };
+
+        let metadata_book = MetadataBook {
+            id: Metadata::ID(Some(self.id)),
+            title: Metadata::Title(Some(title)),
+            artists: Metadata::Artists(names("artists", "artist")?),
+            series: Metadata::Series(names("parodys", "parody")?),
+            groups: Metadata::Groups(names("groups", "group")?),
+            characters: Metadata::Characters(names("characters", "character")?),
+            tags: Metadata::Tags(non_empty(tag::to_madome(self.tags()?))),
+            language: Metadata::Language(
+                self.str_field("language_localname")?
+                    .as_deref()
+                    .map(Language::from),
+            ),
+            content_type: Metadata::ContentType(self.str_field("type")?.map(ContentType::from)),
+            created_at: Metadata::CreatedAt(created_at),
+            thumbnail_url: Metadata::ThumbnailURL(Some(self.thumbnail_url(&files)?)),
+            page_count: Metadata::Page(Some(files.len())),
+        };
+
+        Ok(metadata_book)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use madome_client::book::{ContentType, Language, Metadata};
+
+    use super::GalleryInfo;
+    use super::Parser;
+    use crate::error::{self, ErrorKind};
+    use crate::parser::test_upstream;
+    use crate::tag::{Namespace, Tag};
+
+    #[test]
+    fn parse_gallery_info() -> anyhow::Result<()> {
+        let gallery_info = GalleryInfo::new(1721169).with_upstream(test_upstream());
+
+        let gallery_info = gallery_info.request()?;
+
+        let book = gallery_info.parse()?;
+
+        assert_eq!(Metadata::ID(Some(1721169)), book.id);
+        assert_eq!(
+            Metadata::Title(Some("Fixture Gallery | 픽스쳐 갤러리".to_string())),
+            book.title
+        );
+        assert_eq!(
+            Metadata::Artists(Some(vec!["airandou".to_string()])),
+            book.artists
+        );
+        assert_eq!(Metadata::Series(None), book.series);
+        assert_eq!(Metadata::Groups(None), book.groups);
+        assert_eq!(Metadata::Characters(None), book.characters);
+        assert_eq!(
+            Metadata::Tags(Some(
+                ["sole female ♀", "sole male ♂", "full color"]
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect()
+            )),
+            book.tags
+        );
+        assert_eq!(Metadata::Language(Some(Language::Korean)), book.language);
+        assert_eq!(
+            Metadata::ContentType(Some(ContentType::from("doujinshi".to_string()))),
+            book.content_type
+        );
+        assert_eq!(
+            Metadata::CreatedAt(Some("2020-08-20T12:13:00Z".to_string())),
+            book.created_at
+        );
+        assert_eq!(Metadata::Page(Some(10)), book.page_count);
+        assert_eq!(Some("korean"), gallery_info.language()?.as_deref());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_thumbnail_url() -> anyhow::Result<()> {
+        let gallery_info = GalleryInfo::new(1721169).with_upstream(test_upstream());
+
+        let gallery_info = gallery_info.request()?;
+
+        let files = gallery_info.files()?;
+        let hash = &files[0].hash;
+
+        let expected = format!(
+            "/bigtn/{}/{}/{}.jpg",
+            &hash[hash.len() - 1..],
+            &hash[hash.len() - 3..hash.len() - 1],
+            hash
+        );
+
+        assert_eq!(
+            Metadata::ThumbnailURL(Some(expected)),
+            gallery_info.parse()?.thumbnail_url
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_tags_by_flags() -> anyhow::Result<()> {
+        let gallery_info = GalleryInfo::new(1721169).with_upstream(test_upstream());
+
+        let gallery_info = gallery_info.request()?;
+
+        assert_eq!(
+            vec![
+                Tag::new(Namespace::Female, "sole female"),
+                Tag::new(Namespace::Male, "sole male"),
+                Tag::new(Namespace::Misc, "full color"),
+            ],
+            gallery_info.tags()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_date() {
+        let mut gallery_info = GalleryInfo::new(1721169);
+        gallery_info
+            .set_request_data(
+                r#"{"title":"a","date":"yesterday","files":[{"width":1,"height":1,"hash":"abc","name":"01.jpg"}]}"#
+                    .to_string(),
+            )
+            .unwrap();
+
+        let err = gallery_info.parse().unwrap_err();
+
+        assert_eq!(ErrorKind::ParseInvalidDate, error::kind(&err));
+    }
+
+    #[test]
+    fn layout_changed_without_title() {
+        let mut gallery_info = GalleryInfo::new(1721169);
+        gallery_info
+            .set_request_data(r#"{"files":[]}"#.to_string())
+            .unwrap();
+
+        let err = gallery_info.parse().unwrap_err();
+
+        assert_eq!(ErrorKind::ParseLayoutChanged, error::kind(&err));
+    }
+
+    #[test]
+    fn layout_changed_fields() -> anyhow::Result<()> {
+        let changed = [
+            r#""date":20200820"#,
+            r#""artists":{"artist":"airandou"}"#,
+            r#""groups":[{"group":1}]"#,
+            r#""tags":[{"tag":1},{"tag":"full color"}]"#,
+        ];
+
+        for field in changed.iter() {
+            let mut gallery_info = GalleryInfo::new(1721169);
+            gallery_info.set_request_data(format!(
+                r#"{{"title":"a",{},"files":[{{"width":1,"height":1,"hash":"abc","name":"01.jpg"}}]}}"#,
+                field
+            ))?;
+
+            // files are independent of the metadata
+            assert_eq!(1, gallery_info.files()?.len());
+
+            let err = gallery_info.parse().unwrap_err();
+
+            assert_eq!(
+                ErrorKind::ParseLayoutChanged,
+                error::kind(&err),
+                "{}",
+                field
+            );
+        }
+
+        // missing or null fields are not changed
+        let mut gallery_info = GalleryInfo::new(1721169);
+        gallery_info.set_request_data(
+            r#"{"title":"a","artists":null,"files":[{"width":1,"height":1,"hash":"abc","name":"01.jpg"}]}"#
+                .to_string(),
+        )?;
+
+        let book = gallery_info.parse()?;
+
+        assert_eq!(Metadata::Artists(None), book.artists);
+        assert_eq!(Metadata::CreatedAt(None), book.created_at);
+
+        Ok(())
+    }
+
+    #[test]
+    fn layout_changed_files() {
+        let mut gallery_info = GalleryInfo::new(1721169);
+        gallery_info
+            .set_request_data(r#"{"title":"a","files":{"01.jpg":{}}}"#.to_string())
+            .unwrap();
+
+        let err = gallery_info.files().unwrap_err();
+
+        assert_eq!(ErrorKind::ParseLayoutChanged, error::kind(&err));
+    }
+}
@@ -1,16 +1,26 @@
 use std::char;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
 
 use anyhow;
 use bytes::Bytes;
-use log::{debug, trace};
-use reqwest;
+use log::{debug, trace, warn};
 use serde::{Deserialize, Serialize};
 use serde_json;
 
+use crate::error::{self, ErrorKind, SyncError};
+use crate::http::Request;
+use crate::parser::gallery_info::request_galleryinfo;
 use crate::parser::Parser;
+use crate::subdomain::GgTable;
+use crate::upstream::{self, Upstream, UpstreamEndpoints};
+use crate::utils::get_ext;
+use crate::verify::verify_image;
 
 pub struct Image {
     id: u32,
+    upstream: Arc<Upstream>,
     request_data: Option<Box<String>>,
 }
 
@@ -18,9 +28,15 @@ impl Image {
     pub fn new(id: u32) -> Image {
         Image {
             id,
+            upstream: upstream::default_upstream(),
             request_data: None,
         }
     }
+
+    pub fn with_upstream(mut self, upstream: Arc<Upstream>) -> Image {
+        self.upstream = upstream;
+        self
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -34,6 +50,64 @@ pub struct File {
     pub name: String,
 }
 
+/// # Image Format
+/// Formats of images of hitomi, `Original` is the uploaded format such as jpg
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Avif,
+    Webp,
+    Original,
+}
+
+impl ImageFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Avif => "avif",
+            Self::Webp => "webp",
+            Self::Original => "original",
+        }
+    }
+
+    fn dir(&self) -> &'static str {
+        match self {
+            Self::Avif => "avif",
+            Self::Webp => "webp",
+            Self::Original => "images",
+        }
+    }
+
+    /// `a` of `aa.hitomi.la` for avif and webp, `b` of `ab.hitomi.la` for original
+    fn subdomain_postfix(&self) -> &'static str {
+        match self {
+            Self::Avif | Self::Webp => "a",
+            Self::Original => "b",
+        }
+    }
+}
+
+impl Display for ImageFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "avif" => Ok(Self::Avif),
+            "webp" => Ok(Self::Webp),
+            "original" => Ok(Self::Original),
+            x => Err(anyhow::Error::msg(format!(
+                "Unknown image format `{}`, expected `avif`, `webp` or `original`",
+                x
+            ))),
+        }
+    }
+}
+
 pub type ImageURL = String;
 pub type ThumbnailURL = String;
 
@@ -58,8 +132,115 @@ impl File {
             .expect("Can't get str from OsStr::to_str()")
     } */
 
-    pub fn url(&self, content_id: u32) -> anyhow::Result<(ImageURL, ThumbnailURL)> {
+    pub fn has_avif(&self) -> bool {
+        matches!(self.hasavif, Some(x) if x != 0)
+    }
+
+    /// Formats of `preference` which the file has, `Original` is always the last fallback
+    pub fn formats(&self, preference: &[ImageFormat]) -> Vec<ImageFormat> {
+        let mut r = preference
+            .iter()
+            .copied()
+            .filter(|format| match format {
+                ImageFormat::Avif => self.has_avif(),
+                ImageFormat::Webp => self.has_webp(),
+                ImageFormat::Original => true,
+            })
+            .collect::<Vec<_>>();
+
+        if !r.contains(&ImageFormat::Original) {
+            r.push(ImageFormat::Original);
+        }
+
+        r
+    }
+
+    /// Original image URL of gg.js, or of the legacy rule if gg.js is unavailable
+    pub fn url(
+        &self,
+        upstream: &Upstream,
+        content_id: u32,
+    ) -> anyhow::Result<(ImageURL, ThumbnailURL)> {
         trace!("File::url()");
+        let gg = upstream.subdomains.table(upstream);
+
+        let image_url = self.image_url(
+            &upstream.endpoints,
+            content_id,
+            gg.as_deref(),
+            ImageFormat::Original,
+        )?;
+        let thumbnail_url = self.thumbnail_url(&upstream.endpoints, content_id)?;
+
+        Ok((image_url, thumbnail_url))
+    }
+
+    fn image_url(
+        &self,
+        endpoints: &UpstreamEndpoints,
+        content_id: u32,
+        gg: Option<&GgTable>,
+        format: ImageFormat,
+    ) -> anyhow::Result<ImageURL> {
+        let (subdomain, dir) = match gg {
+            Some(gg) => self.gg_route(content_id, gg)?,
+            None => self.legacy_route(content_id)?,
+        };
+
+        let ext = match format {
+            ImageFormat::Original => get_ext(&self.name).unwrap_or("jpg"),
+            format => format.as_str(),
+        };
+
+        let image_url = endpoints.image(
+            &format!("{}{}", subdomain, format.subdomain_postfix()),
+            &format!("{}/{}/{}.{}", format.dir(), dir, self.hash, ext),
+        );
+
+        debug!("image_url = {}", image_url);
+
+        Ok(image_url)
+    }
+
+    fn thumbnail_url(
+        &self,
+        endpoints: &UpstreamEndpoints,
+        content_id: u32,
+    ) -> anyhow::Result<ThumbnailURL> {
+        let postfix = self.postfix(content_id)?;
+
+        let thumbnail_url = endpoints.thumbnail(&format!(
+            "bigtn/{}/{}/{}.jpg",
+            &postfix[2..],
+            &postfix[..2],
+            self.hash
+        ));
+
+        debug!("thumbnail_url = {}", thumbnail_url);
+
+        Ok(thumbnail_url)
+    }
+
+    /// The last 3 characters of hash
+    fn postfix(&self, content_id: u32) -> anyhow::Result<&str> {
+        self.hash
+            .get(self.hash.len().saturating_sub(3)..)
+            .filter(|postfix| postfix.len() == 3 && postfix.is_ascii())
+            .ok_or_else(|| SyncError::layout_changed(content_id, "hash", "files[].hash").into())
+    }
+
+    /// (subdomain, directory) of gg.js
+    fn gg_route(&self, content_id: u32, gg: &GgTable) -> anyhow::Result<(String, String)> {
+        let layout_changed = || SyncError::layout_changed(content_id, "hash", "files[].hash");
+
+        let g = GgTable::s(&self.hash).ok_or_else(layout_changed)?;
+        let subdomain = char::from_u32(97 + gg.m(g)).ok_or_else(layout_changed)?;
+
+        Ok((subdomain.to_string(), format!("{}{}", gg.b(), g)))
+    }
+
+    /// (subdomain, directory) of `number_of_frontends` of hitomi before gg.js
+    fn legacy_route(&self, content_id: u32) -> anyhow::Result<(String, String)> {
         let id_string = content_id.to_string();
         let mut id_chars = id_string.chars();
 
@@ -85,7 +266,7 @@ impl File {
 
         debug!("1st subdomain {}", subdomain);
 
-        let postfix = &self.hash[self.hash.len() - 3..].chars().collect::<Vec<_>>();
+        let postfix = self.postfix(content_id)?.chars().collect::<Vec<_>>();
 
         debug!("hash {}", self.hash);
         debug!("postfix {:?}", postfix);
@@ -111,83 +292,102 @@ impl File {
 
         debug!("2nd subdomain {}", subdomain);
 
-        /* let image_url = if self.has_webp() == false {
-            format!(
-                "https://{}b.hitomi.la/images/{}/{}{}/{}.{}",
-                subdomain,
-                postfix[2],
-                postfix[0],
-                postfix[1],
-                self.hash,
-                self.name.split(".").last().unwrap()
-            )
-        } else if self.hash.as_str() == "" {
-            format!("https://{}a.hitomi.la/webp/{}.webp", subdomain, self.name)
-        } else if self.hash.len() < 3 {
-            format!("https://{}a.hitomi.la/webp/{}.webp", subdomain, self.hash)
-        } else {
-            format!(
-                "https://{}a.hitomi.la/webp/{}/{}{}/{}.webp",
-                subdomain, postfix[2], postfix[0], postfix[1], self.hash
-            )
-        }; */
-
-        let image_url = format!(
-            "https://{}b.hitomi.la/images/{}/{}{}/{}.{}",
+        Ok((
             subdomain,
-            postfix[2],
-            postfix[0],
-            postfix[1],
-            self.hash,
-            self.name.split(".").last().unwrap()
-        );
+            format!("{}/{}{}", postfix[2], postfix[0], postfix[1]),
+        ))
+    }
 
-        let thumbnail_url = format!(
-            "https://tn.hitomi.la/bigtn/{}/{}{}/{}.jpg",
-            postfix[2], postfix[0], postfix[1], self.hash
-        );
+    /// (URL, buf)
+    ///
+    /// Image of the first format found of `File::formats(formats)`,
+    /// refreshes gg.js and retries once if every format is not found by its subdomain
+    pub fn download(
+        &self,
+        upstream: &Upstream,
+        content_id: u32,
+        is_thumbnail: bool,
+        formats: &[ImageFormat],
+    ) -> anyhow::Result<(String, Bytes)> {
+        if is_thumbnail {
+            let thumbnail_url = self.thumbnail_url(&upstream.endpoints, content_id)?;
+            let r = self.download_(upstream, content_id, &thumbnail_url, None)?;
+            return Ok((thumbnail_url, r));
+        }
 
-        debug!("image_url = {}", image_url);
-        debug!("thumbnail_url = {}", thumbnail_url);
+        let gg = upstream.subdomains.table(upstream);
 
-        Ok((image_url, thumbnail_url))
-    }
+        match self.download_formats(upstream, content_id, gg.as_deref(), formats) {
+            Err(err) if error::kind(&err) == ErrorKind::UpstreamNotFound => {
+                upstream.subdomains.invalidate(gg.as_ref());
 
-    /// (URL, buf)
-    pub fn download(&self, content_id: u32, is_thumbnail: bool) -> anyhow::Result<(String, Bytes)> {
-        let (image_url, thumbnail_url) = self.url(content_id)?;
+                let refreshed = upstream.subdomains.table(upstream);
 
-        if is_thumbnail {
-            let r = self.download_(content_id, &thumbnail_url)?;
-            Ok((thumbnail_url, r))
-        } else {
-            let r = self.download_(content_id, &image_url)?;
-            Ok((image_url, r))
+                if refreshed == gg {
+                    return Err(err);
+                }
+
+                warn!("{}: gg.js is changed, retry: {}", content_id, err);
+
+                self.download_formats(upstream, content_id, refreshed.as_deref(), formats)
+            }
+            r => r,
         }
     }
 
-    fn download_<U: reqwest::IntoUrl>(&self, content_id: u32, url: U) -> anyhow::Result<Bytes> {
+    fn download_formats(
+        &self,
+        upstream: &Upstream,
+        content_id: u32,
+        gg: Option<&GgTable>,
+        formats: &[ImageFormat],
+    ) -> anyhow::Result<(String, Bytes)> {
+        let mut not_found = None;
+
+        for format in self.formats(formats) {
+            let image_url = self.image_url(&upstream.endpoints, content_id, gg, format)?;
+
+            match self.download_(upstream, content_id, &image_url, self.size()) {
+                Ok(r) => return Ok((image_url, r)),
+                Err(err) if error::kind(&err) == ErrorKind::UpstreamNotFound => {
+                    debug!("{}: Fallback from {}: {}", content_id, format, err);
+                    not_found = Some(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(not_found.expect("File::formats() has Original at least"))
+    }
+
+    /// `(width, height)` to verify, `None` if unknown
+    fn size(&self) -> Option<(u32, u32)> {
+        Some((self.width, self.height)).filter(|(width, height)| *width > 0 && *height > 0)
+    }
+
+    /// Verified body of `url`, corrupt images are retryable
+    fn download_(
+        &self,
+        upstream: &Upstream,
+        content_id: u32,
+        url: &str,
+        size: Option<(u32, u32)>,
+    ) -> anyhow::Result<Bytes> {
         trace!("File::download()");
-        let client = reqwest::blocking::Client::builder().build()?;
-
-        let response = client
-            .get(url)
-            .header(
-                "Referer",
-                format!("https://hitomi.la/reader/{}.html", content_id),
-            )
-            .send()?;
-
-        if response.status().is_success() {
-            let bytes = response.bytes()?;
-            Ok(bytes)
-        } else {
-            // debug!("{}", response.text()?);
+        let request = Request::get(url).header(
+            "Referer",
+            upstream
+                .endpoints
+                .hitomi(&format!("reader/{}.html", content_id)),
+        );
 
-            Err(anyhow::Error::msg(format!(
-                "Image Download Error! {}",
-                response.status().to_string()
-            )))
+        let response = upstream.send(request)?;
+
+        if response.is_success() {
+            verify_image(url, &response.body, size)?;
+            Ok(response.body)
+        } else {
+            Err(SyncError::from_status(url, response.status).into())
         }
     }
 }
@@ -195,6 +395,17 @@ impl File {
 #[derive(Serialize, Deserialize, Debug)]
 struct ImageInfo {
     files: Vec<File>,
+    #[serde(default)]
+    language: Option<String>,
+}
+
+impl Image {
+    /// `language` of galleryinfo such as `korean`
+    pub fn language(&self) -> anyhow::Result<Option<String>> {
+        let request_data = self.request_data()?;
+
+        Ok(serde_json::from_str::<ImageInfo>(request_data)?.language)
+    }
 }
 
 impl Parser for Image {
@@ -205,37 +416,23 @@ impl Parser for Image {
         trace!("Image::request_data()");
         match self.request_data {
             Some(ref rd) => Ok(rd),
-            None => Err(anyhow::Error::msg("Can't get request_data")),
+            None => Err(SyncError::NotRequested.into()),
         }
     }
 
     fn url(&self) -> anyhow::Result<String> {
         trace!("Image::url()");
-        Ok(format!("https://ltn.hitomi.la/galleries/{}.js", self.id))
+        Ok(self
+            .upstream
+            .endpoints
+            .ltn(&format!("galleries/{}.js", self.id)))
     }
 
     fn request(mut self) -> anyhow::Result<Box<Self>> {
         trace!("Image::request()");
-        let client = reqwest::blocking::Client::builder().build()?;
-
-        let response = client.get(&self.url()?).send()?;
-
-        if !response.status().is_success() {
-            return Err(anyhow::Error::msg(response.status().to_string()));
-        }
-
-        let rd = response.text()?;
+        let rd = request_galleryinfo(&self.upstream, self.id)?;
 
-        // panic
-        let i = rd.find("=").ok_or_else(|| {
-            anyhow::Error::msg(format!(
-                "error occurs `request_data.find(\"=\")` in parser::Image::request(), {}",
-                rd
-            ))
-        })?;
-        let rd = &rd[i + 1..];
-
-        self.request_data = Some(Box::new(rd.to_string()));
+        self.request_data = Some(Box::new(rd));
         Ok(Box::new(self))
     }
 
@@ -243,7 +440,7 @@ impl Parser for Image {
         trace!("Image::parse()");
         let ref request_data = match self.request_data {
             Some(ref rd) => rd,
-            None => return Err(anyhow::Error::msg("Can't get request_data")),
+            None => return Err(SyncError::NotRequested.into()),
         };
 
         let image_info = serde_json::from_str::<'_, ImageInfo>(&request_data)?;
@@ -258,18 +455,101 @@ impl Parser for Image {
 mod tests {
     use anyhow;
 
-    use super::Image;
     use super::Parser;
+    use super::{File, Image, ImageFormat};
+    use crate::parser::test_upstream;
+    use crate::subdomain::GgTable;
+    use crate::upstream::UpstreamEndpoints;
 
     #[test]
     fn parse_image_files_info() -> anyhow::Result<()> {
-        let image_parser = Image::new(1721169);
+        let image_parser = Image::new(1721169).with_upstream(test_upstream());
 
         let image_parser = image_parser.request()?;
 
         let image_files_info = image_parser.parse()?;
 
         assert_eq!(10, image_files_info.len());
+        assert_eq!(Some("korean"), image_parser.language()?.as_deref());
+
+        Ok(())
+    }
+
+    fn file() -> File {
+        File {
+            width: 1280,
+            height: 1810,
+            hash: "2fd1808fbf15b1901bb6eb751ee88a517bd67ea44061d74f6bd9e4c63ae620ae".to_string(),
+            haswebp: Some(1),
+            hasavifsmalltn: None,
+            hasavif: None,
+            name: "01.jpg".to_string(),
+        }
+    }
+
+    #[test]
+    fn url_of_gg_js() -> anyhow::Result<()> {
+        let endpoints = UpstreamEndpoints::default();
+        // 0ae => 0xe0a = 3594
+        let gg = GgTable::parse("var o = 0; case 3594: o = 1; break; b: '1650348721/'")
+            .ok_or_else(|| anyhow::Error::msg("gg.js"))?;
+
+        let file = file();
+
+        assert_eq!(
+            "https://bb.hitomi.la/images/1650348721/3594/2fd1808fbf15b1901bb6eb751ee88a517bd67ea44061d74f6bd9e4c63ae620ae.jpg",
+            file.image_url(&endpoints, 1724122, Some(&gg), ImageFormat::Original)?
+        );
+        assert_eq!(
+            "https://ba.hitomi.la/webp/1650348721/3594/2fd1808fbf15b1901bb6eb751ee88a517bd67ea44061d74f6bd9e4c63ae620ae.webp",
+            file.image_url(&endpoints, 1724122, Some(&gg), ImageFormat::Webp)?
+        );
+        assert_eq!(
+            "https://ba.hitomi.la/avif/1650348721/3594/2fd1808fbf15b1901bb6eb751ee88a517bd67ea44061d74f6bd9e4c63ae620ae.avif",
+            file.image_url(&endpoints, 1724122, Some(&gg), ImageFormat::Avif)?
+        );
+        assert_eq!(
+            "https://tn.hitomi.la/bigtn/e/0a/2fd1808fbf15b1901bb6eb751ee88a517bd67ea44061d74f6bd9e4c63ae620ae.jpg",
+            file.thumbnail_url(&endpoints, 1724122)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn url_of_legacy_rule() -> anyhow::Result<()> {
+        let endpoints = UpstreamEndpoints::default();
+
+        assert_eq!(
+            "https://cb.hitomi.la/images/e/0a/2fd1808fbf15b1901bb6eb751ee88a517bd67ea44061d74f6bd9e4c63ae620ae.jpg",
+            file().image_url(&endpoints, 1724122, None, ImageFormat::Original)?
+        );
+        assert_eq!(
+            "https://ca.hitomi.la/webp/e/0a/2fd1808fbf15b1901bb6eb751ee88a517bd67ea44061d74f6bd9e4c63ae620ae.webp",
+            file().image_url(&endpoints, 1724122, None, ImageFormat::Webp)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn formats_by_preference() -> anyhow::Result<()> {
+        let preference = ["avif", "webp", "original"]
+            .iter()
+            .map(|x| x.parse())
+            .collect::<anyhow::Result<Vec<ImageFormat>>>()?;
+
+        // haswebp without hasavif
+        assert_eq!(
+            vec![ImageFormat::Webp, ImageFormat::Original],
+            file().formats(&preference)
+        );
+        assert_eq!(vec![ImageFormat::Original], file().formats(&[]));
+        assert_eq!(
+            vec![ImageFormat::Webp, ImageFormat::Original],
+            file().formats(&[ImageFormat::Webp])
+        );
+        assert!("jpeg".parse::<ImageFormat>().is_err());
 
         Ok(())
     }
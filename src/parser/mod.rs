@@ -2,13 +2,41 @@ use anyhow;
 
 mod gallery;
 mod gallery_block;
+mod gallery_info;
 mod image;
 mod nozomi;
 
 pub use gallery::Gallery;
 pub use gallery_block::GalleryBlock;
-pub use image::{File, Image};
-pub use nozomi::Nozomi;
+pub use gallery_info::GalleryInfo;
+pub use image::{File, Image, ImageFormat};
+pub use nozomi::{Nozomi, NozomiSource};
+
+/// Replays fixtures of `tests/fixtures/hitomi`,
+/// or requests hitomi.la with `live-tests` feature
+///
+/// `RECORD_FIXTURES=1 cargo test --features live-tests` rewrites the fixtures
+#[cfg(test)]
+pub(crate) fn test_upstream() -> std::sync::Arc<crate::upstream::Upstream> {
+    use std::sync::Arc;
+
+    use crate::http::{self, FixtureTransport, Transport};
+    use crate::upstream::{Upstream, UpstreamEndpoints};
+
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/hitomi");
+
+    let transport: Arc<dyn Transport> = if cfg!(feature = "live-tests") {
+        if std::env::var("RECORD_FIXTURES").is_ok() {
+            Arc::new(FixtureTransport::record(dir, http::default_transport()))
+        } else {
+            http::default_transport()
+        }
+    } else {
+        Arc::new(FixtureTransport::replay(dir))
+    };
+
+    Arc::new(Upstream::new(transport, UpstreamEndpoints::default()))
+}
 
 pub trait Parser {
     // self.request_data;
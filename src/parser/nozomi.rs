@@ -1,12 +1,123 @@
 use std::convert::TryInto;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
 
 use anyhow;
 use bytes::Bytes;
 use log::{debug, trace};
-use madome_client::book::Language;
-use reqwest;
+use reqwest::StatusCode;
 
 use super::Parser;
+use crate::error::SyncError;
+use crate::http::Request;
+use crate::tag::Tag;
+use crate::upstream::{self, Upstream};
+
+/// # Nozomi Source
+/// Nozomi files of hitomi, sorted by newest
+///
+/// * `index-korean.nozomi`
+/// * `tag/female:sole female-korean.nozomi`
+/// * `artist/airandou-korean.nozomi`
+/// * `series/sword art online-korean.nozomi`
+/// * `group/haniya-korean.nozomi`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NozomiSource {
+    Index { language: String },
+    Tag { tag: String, language: String },
+    Artist { artist: String, language: String },
+    Series { series: String, language: String },
+    Group { group: String, language: String },
+}
+
+impl NozomiSource {
+    pub fn index(language: impl Into<String>) -> Self {
+        Self::Index {
+            language: language.into().to_lowercase(),
+        }
+    }
+
+    /// Parses `tag:<tag>`, `artist:<artist>`, `series:<series>`, `group:<group>` or `index`
+    ///
+    /// ```
+    /// use madome_synchronizer::parser::NozomiSource;
+    ///
+    /// let source = NozomiSource::parse("tag:female:sole female", "korean").unwrap();
+    ///
+    /// assert_eq!("tag/female:sole female-korean", source.to_string());
+    /// ```
+    pub fn parse(spec: &str, language: &str) -> anyhow::Result<Self> {
+        let language = language.to_lowercase();
+
+        if spec == "index" {
+            return Ok(Self::Index { language });
+        }
+
+        let (kind, name) = match spec.find(':') {
+            Some(i) => (&spec[..i], spec[i + 1..].trim().to_lowercase()),
+            None => {
+                return Err(anyhow::Error::msg(format!(
+                    "Can't parse nozomi source `{}`, expected `<kind>:<name>`",
+                    spec
+                )))
+            }
+        };
+
+        if name.is_empty() {
+            return Err(anyhow::Error::msg(format!(
+                "Can't parse nozomi source `{}`, name is empty",
+                spec
+            )));
+        }
+
+        match kind {
+            // `sole female ♀` of Madome is `female:sole female` of hitomi
+            "tag" => Ok(Self::Tag {
+                tag: name.parse::<Tag>()?.hitomi(),
+                language,
+            }),
+            "artist" => Ok(Self::Artist {
+                artist: name,
+                language,
+            }),
+            "series" => Ok(Self::Series {
+                series: name,
+                language,
+            }),
+            "group" => Ok(Self::Group {
+                group: name,
+                language,
+            }),
+            _ => Err(anyhow::Error::msg(format!(
+                "Can't parse nozomi source `{}`, unknown kind `{}`",
+                spec, kind
+            ))),
+        }
+    }
+
+    pub fn language(&self) -> &str {
+        match self {
+            Self::Index { language }
+            | Self::Tag { language, .. }
+            | Self::Artist { language, .. }
+            | Self::Series { language, .. }
+            | Self::Group { language, .. } => language,
+        }
+    }
+}
+
+/// Path of nozomi file without extension
+impl Display for NozomiSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index { language } => write!(f, "index-{}", language),
+            Self::Tag { tag, language } => write!(f, "tag/{}-{}", tag, language),
+            Self::Artist { artist, language } => write!(f, "artist/{}-{}", artist, language),
+            Self::Series { series, language } => write!(f, "series/{}-{}", series, language),
+            Self::Group { group, language } => write!(f, "group/{}-{}", group, language),
+        }
+    }
+}
 
 /// # Nozomi Parser
 /// Not needed VPN for Nozomi Parser
@@ -15,19 +126,30 @@ use super::Parser;
 pub struct Nozomi {
     page: usize,
     per_page: usize,
-    language: String,
+    source: NozomiSource,
+    upstream: Arc<Upstream>,
     request_data: Option<Box<Bytes>>,
 }
 
 impl Nozomi {
-    pub fn new(page: usize, per_page: usize, language: Language) -> Nozomi {
+    pub fn new(page: usize, per_page: usize, language: impl Into<String>) -> Nozomi {
+        Self::from_source(NozomiSource::index(language), page, per_page)
+    }
+
+    pub fn from_source(source: NozomiSource, page: usize, per_page: usize) -> Nozomi {
         Nozomi {
             page,
             per_page,
-            language: language.into(),
+            source,
+            upstream: upstream::default_upstream(),
             request_data: None,
         }
     }
+
+    pub fn with_upstream(mut self, upstream: Arc<Upstream>) -> Nozomi {
+        self.upstream = upstream;
+        self
+    }
 }
 
 impl Parser for Nozomi {
@@ -37,34 +159,39 @@ impl Parser for Nozomi {
     fn request_data(&self) -> anyhow::Result<&Box<Self::RequestData>> {
         match self.request_data {
             Some(ref rd) => Ok(rd),
-            None => Err(anyhow::Error::msg("Can't get request_data")),
+            None => Err(SyncError::NotRequested.into()),
         }
     }
 
     fn url(&self) -> anyhow::Result<String> {
-        Ok(format!(
-            "https://ltn.hitomi.la/index-{}.nozomi",
-            self.language.to_lowercase()
-        ))
+        Ok(self
+            .upstream
+            .endpoints
+            .ltn(&format!("{}.nozomi", self.source)))
     }
 
     fn request(mut self) -> anyhow::Result<Box<Self>> {
         trace!("Nozomi::request()");
-        let client = reqwest::blocking::Client::builder().build()?;
-
         let start_bytes = (self.page - 1) * self.per_page * 4;
         let end_bytes = start_bytes + self.per_page * 4 - 1;
 
         debug!("start_bytes = {}", start_bytes);
         debug!("end_bytes = {}", end_bytes);
 
-        let bytes = client
-            .get(&self.url()?)
-            .header("Range", format!("bytes={}-{}", start_bytes, end_bytes))
-            .send()?
-            .bytes()?;
+        let url = self.url()?;
+        let request =
+            Request::get(&url).header("Range", format!("bytes={}-{}", start_bytes, end_bytes));
+
+        let response = self.upstream.send(request)?;
 
-        self.request_data = Some(Box::new(bytes));
+        // out of range of nozomi is the end of nozomi, its body is an error page
+        let body = match response.status {
+            StatusCode::RANGE_NOT_SATISFIABLE => Bytes::new(),
+            _ if response.is_success() => response.body,
+            status => return Err(SyncError::from_status(url, status).into()),
+        };
+
+        self.request_data = Some(Box::new(body));
         Ok(Box::new(self))
     }
 
@@ -103,11 +230,60 @@ mod test {
     use madome_client::book::Language;
 
     use super::Nozomi;
+    use super::NozomiSource;
     use super::Parser;
+    use crate::parser::test_upstream;
+
+    #[test]
+    fn nozomi_source_url() -> anyhow::Result<()> {
+        let korean = Nozomi::new(1, 25, Language::Korean);
+        let artist = Nozomi::from_source(NozomiSource::parse("artist:airandou", "all")?, 1, 25);
+
+        assert_eq!("https://ltn.hitomi.la/index-korean.nozomi", korean.url()?);
+        assert_eq!(
+            "https://ltn.hitomi.la/artist/airandou-all.nozomi",
+            artist.url()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_nozomi_source() -> anyhow::Result<()> {
+        assert_eq!(
+            NozomiSource::Tag {
+                tag: "female:sole female".to_string(),
+                language: "korean".to_string()
+            },
+            NozomiSource::parse("tag:Female:Sole Female", "Korean")?
+        );
+        assert_eq!(
+            "tag/female:sole female-korean",
+            NozomiSource::parse("tag:sole female ♀", "korean")?.to_string()
+        );
+        assert_eq!(
+            "series/sword art online-english",
+            NozomiSource::parse("series:sword art online", "english")?.to_string()
+        );
+        assert_eq!(
+            "group/haniya-japanese",
+            NozomiSource::parse("group:haniya", "japanese")?.to_string()
+        );
+        assert_eq!(
+            NozomiSource::index("korean"),
+            NozomiSource::parse("index", "korean")?
+        );
+
+        assert!(NozomiSource::parse("character:lum", "korean").is_err());
+        assert!(NozomiSource::parse("artist:", "korean").is_err());
+        assert!(NozomiSource::parse("airandou", "korean").is_err());
+
+        Ok(())
+    }
 
     #[test]
     fn parse_nozomi() -> anyhow::Result<()> {
-        let nozomi_parser = Nozomi::new(1, 25, Language::Korean);
+        let nozomi_parser = Nozomi::new(1, 25, Language::Korean).with_upstream(test_upstream());
 
         let nozomi_parser = nozomi_parser.request()?;
 
@@ -120,11 +296,25 @@ mod test {
 
     #[test]
     fn parse_nozomi_index_out_of_bounds() -> anyhow::Result<()> {
-        let nozomi_parser = Nozomi::new(20, 1000000, Language::Korean);
+        let nozomi_parser =
+            Nozomi::new(20, 1000000, Language::Korean).with_upstream(test_upstream());
 
         let nozomi_parser = nozomi_parser.request()?;
         let _pd = nozomi_parser.parse()?;
 
         Ok(())
     }
+
+    #[test]
+    fn ignore_body_of_range_not_satisfiable() -> anyhow::Result<()> {
+        // the fixture is an error page of nginx
+        let nozomi_parser =
+            Nozomi::new(21, 1000000, Language::Korean).with_upstream(test_upstream());
+
+        let nozomi_parser = nozomi_parser.request()?;
+
+        assert!(nozomi_parser.parse()?.is_empty());
+
+        Ok(())
+    }
 }
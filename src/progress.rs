@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+/// Pages set between writes of the file, pages lost by crashes are found by `HashIndex`
+const SYNCHRONIZE_EVERY: usize = 16;
+
+/// # Page Progress
+/// Uploaded pages of a gallery, so retries upload the missing pages only
+///
+/// `{progress_dir}/{id}.json`, removed after `image_list.txt` is uploaded
+///
+/// ```json
+/// { "page_count": 3, "thumbnail": true, "pages": { "1": "image/library/1724122/1.jpg" } }
+/// ```
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PageProgress {
+    #[serde(skip)]
+    path: PathBuf,
+    page_count: usize,
+    thumbnail: bool,
+    /// page => url_path
+    pages: BTreeMap<usize, String>,
+    /// Pages set after the last write
+    #[serde(skip)]
+    unsynchronized: usize,
+}
+
+impl PageProgress {
+    /// Starts over if the file doesn't exist, can't be parsed or the page count is changed
+    pub fn load(dir: impl AsRef<Path>, id: u32, page_count: usize) -> anyhow::Result<Self> {
+        let path = dir.as_ref().join(format!("{}.json", id));
+
+        let loaded = match fs::read_to_string(&path) {
+            Ok(text) if !text.trim().is_empty() => match serde_json::from_str::<Self>(&text) {
+                Ok(loaded) => Some(loaded),
+                Err(err) => {
+                    warn!(
+                        "{}: Start over, can't parse {}: {}",
+                        id,
+                        path.display(),
+                        err
+                    );
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        let progress = match loaded {
+            Some(loaded) if loaded.page_count == page_count => {
+                debug!(
+                    "{}: Resume from {} / {} pages",
+                    id,
+                    loaded.pages.len(),
+                    page_count
+                );
+                Self { path, ..loaded }
+            }
+            _ => Self {
+                path,
+                page_count,
+                ..Self::default()
+            },
+        };
+
+        Ok(progress)
+    }
+
+    pub fn has_thumbnail(&self) -> bool {
+        self.thumbnail
+    }
+
+    pub fn set_thumbnail(&mut self) {
+        self.thumbnail = true;
+    }
+
+    pub fn has_page(&self, page: usize) -> bool {
+        self.pages.contains_key(&page)
+    }
+
+    pub fn set_page(&mut self, page: usize, url_path: String) {
+        self.pages.insert(page, url_path);
+        self.unsynchronized += 1;
+    }
+
+    /// url_path of every pages in order, `None` if any page is missing
+    pub fn image_list(&self) -> Option<Vec<String>> {
+        (1..=self.page_count)
+            .map(|page| self.pages.get(&page).cloned())
+            .collect()
+    }
+
+    /// Writes the file every `SYNCHRONIZE_EVERY` pages, instead of every page
+    pub fn synchronize_batch(&mut self) -> anyhow::Result<()> {
+        if self.unsynchronized < SYNCHRONIZE_EVERY {
+            return Ok(());
+        }
+
+        self.synchronize()
+    }
+
+    /// Writes `{id}.json.tmp` and renames it, so the file is never truncated
+    pub fn synchronize(&mut self) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let tmp = self.path.with_extension("json.tmp");
+
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(&tmp, &self.path)?;
+
+        self.unsynchronized = 0;
+
+        Ok(())
+    }
+
+    /// Every pages are synchronized with Madome
+    pub fn remove(self) -> anyhow::Result<()> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::{PageProgress, SYNCHRONIZE_EVERY};
+
+    #[test]
+    fn resume_pages() -> anyhow::Result<()> {
+        let dir = env::temp_dir().join(format!("madome-progress-{}", std::process::id()));
+
+        let mut progress = PageProgress::load(&dir, 1724122, 3)?;
+
+        progress.set_thumbnail();
+        progress.set_page(1, "image/library/1724122/1.jpg".to_string());
+        progress.set_page(3, "image/library/1724122/3.jpg".to_string());
+        progress.synchronize()?;
+
+        assert_eq!(None, progress.image_list());
+
+        let mut progress = PageProgress::load(&dir, 1724122, 3)?;
+
+        assert!(progress.has_thumbnail());
+        assert!(progress.has_page(1));
+        assert!(!progress.has_page(2));
+
+        progress.set_page(2, "image/library/1724122/2.webp".to_string());
+
+        assert_eq!(
+            Some(vec![
+                "image/library/1724122/1.jpg".to_string(),
+                "image/library/1724122/2.webp".to_string(),
+                "image/library/1724122/3.jpg".to_string(),
+            ]),
+            progress.image_list()
+        );
+
+        // the gallery is changed
+        let progress = PageProgress::load(&dir, 1724122, 4)?;
+
+        assert!(!progress.has_thumbnail());
+        assert!(!progress.has_page(1));
+
+        progress.remove()?;
+        PageProgress::load(&dir, 1724122, 3)?.remove()?;
+
+        assert!(!dir.join("1724122.json").exists());
+
+        fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn synchronize_in_batches() -> anyhow::Result<()> {
+        let dir = env::temp_dir().join(format!("madome-progress-batch-{}", std::process::id()));
+        let page_count = SYNCHRONIZE_EVERY * 2;
+
+        let mut progress = PageProgress::load(&dir, 1724122, page_count)?;
+
+        for page in 1..SYNCHRONIZE_EVERY {
+            progress.set_page(page, format!("image/library/1724122/{}.jpg", page));
+            progress.synchronize_batch()?;
+        }
+
+        assert!(!dir.join("1724122.json").exists());
+
+        progress.set_page(
+            SYNCHRONIZE_EVERY,
+            format!("image/library/1724122/{}.jpg", SYNCHRONIZE_EVERY),
+        );
+        progress.synchronize_batch()?;
+
+        let loaded = PageProgress::load(&dir, 1724122, page_count)?;
+
+        assert!(loaded.has_page(SYNCHRONIZE_EVERY));
+        assert!(!loaded.has_page(SYNCHRONIZE_EVERY + 1));
+
+        fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn start_over_truncated() -> anyhow::Result<()> {
+        let dir = env::temp_dir().join(format!("madome-progress-truncated-{}", std::process::id()));
+
+        let mut progress = PageProgress::load(&dir, 1724122, 3)?;
+
+        progress.set_page(1, "image/library/1724122/1.jpg".to_string());
+        progress.synchronize()?;
+
+        assert!(!dir.join("1724122.json.tmp").exists());
+
+        let text = fs::read_to_string(dir.join("1724122.json"))?;
+        fs::write(dir.join("1724122.json"), &text[..text.len() / 2])?;
+
+        let progress = PageProgress::load(&dir, 1724122, 3)?;
+
+        assert!(!progress.has_page(1));
+
+        fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::error;
+use crate::stage::StageTiming;
+
+/// # Transfer
+/// Bytes of a gallery transferred during a run
+#[derive(Debug, Default)]
+pub struct Transfer {
+    downloaded: AtomicU64,
+    uploaded: AtomicU64,
+}
+
+impl Transfer {
+    pub fn add_downloaded(&self, bytes: usize) {
+        self.downloaded.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_uploaded(&self, bytes: usize) {
+        self.uploaded.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded.load(Ordering::Relaxed)
+    }
+
+    pub fn uploaded(&self) -> u64 {
+        self.uploaded.load(Ordering::Relaxed)
+    }
+}
+
+/// # Run Kind
+/// A gallery is synchronized by two runs, images and book
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunKind {
+    Images,
+    Book,
+}
+
+/// # Run Report
+/// Last run of images or book
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunReport {
+    /// RFC 3339 of UTC
+    pub finished_at: String,
+    pub duration_ms: u64,
+    /// `ok` or `error`
+    pub result: String,
+    pub error_kind: Option<String>,
+    pub error: Option<String>,
+    pub page_count: Option<usize>,
+    pub downloaded_bytes: u64,
+    pub uploaded_bytes: u64,
+    /// Stage name => timing, retries of each stage are included
+    pub stages: BTreeMap<String, StageTiming>,
+}
+
+impl RunReport {
+    pub fn new<T>(
+        r: &anyhow::Result<T>,
+        duration: Duration,
+        page_count: Option<usize>,
+        transfer: &Transfer,
+        stages: BTreeMap<String, StageTiming>,
+    ) -> Self {
+        let (result, error_kind, error) = match r {
+            Ok(_) => ("ok", None, None),
+            Err(err) => (
+                "error",
+                Some(error::kind(err).to_string()),
+                Some(err.to_string()),
+            ),
+        };
+
+        Self {
+            finished_at: OffsetDateTime::now_utc().format("%Y-%m-%dT%H:%M:%SZ"),
+            duration_ms: duration.as_millis() as u64,
+            result: result.to_string(),
+            error_kind,
+            error,
+            page_count,
+            downloaded_bytes: transfer.downloaded(),
+            uploaded_bytes: transfer.uploaded(),
+            stages,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.result == "ok"
+    }
+}
+
+/// # Report
+/// Last runs of a gallery, `{report_dir}/{id}.json`
+///
+/// ```json
+/// {
+///   "id": 1724122,
+///   "images": {
+///     "finished_at": "2021-01-02T03:04:05Z", "duration_ms": 5210, "result": "ok",
+///     "error_kind": null, "error": null, "page_count": 3,
+///     "downloaded_bytes": 3145728, "uploaded_bytes": 3145728,
+///     "stages": { "add_images": { "calls": 3, "retries": 1, "errors": 0, "duration_ms": 4800, "max_duration_ms": 2100 } }
+///   },
+///   "book": null
+/// }
+/// ```
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub id: u32,
+    pub images: Option<RunReport>,
+    pub book: Option<RunReport>,
+}
+
+impl Report {
+    fn path(dir: impl AsRef<Path>, id: u32) -> PathBuf {
+        dir.as_ref().join(format!("{}.json", id))
+    }
+
+    /// `None` if `id` has never been synchronized
+    pub fn load(dir: impl AsRef<Path>, id: u32) -> anyhow::Result<Option<Self>> {
+        match fs::read_to_string(Self::path(dir, id)) {
+            Ok(text) => Ok(Some(serde_json::from_str(&text)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replaces the run of `kind`, keeping the other run
+    pub fn write(
+        dir: impl AsRef<Path>,
+        id: u32,
+        kind: RunKind,
+        run: RunReport,
+    ) -> anyhow::Result<()> {
+        let dir = dir.as_ref();
+        let mut report = Self::load(dir, id)?.unwrap_or_else(|| Self {
+            id,
+            ..Self::default()
+        });
+
+        match kind {
+            RunKind::Images => report.images = Some(run),
+            RunKind::Book => report.book = Some(run),
+        }
+
+        fs::create_dir_all(dir)?;
+        fs::write(Self::path(dir, id), serde_json::to_string_pretty(&report)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs;
+    use std::time::Duration;
+
+    use super::{Report, RunKind, RunReport, Transfer};
+    use crate::error::SyncError;
+    use crate::stage::StageTiming;
+
+    #[test]
+    fn write_runs() -> anyhow::Result<()> {
+        let dir = env::temp_dir().join(format!("madome-report-{}", std::process::id()));
+
+        assert_eq!(None, Report::load(&dir, 1724122)?);
+
+        let transfer = Transfer::default();
+        transfer.add_downloaded(2048);
+        transfer.add_downloaded(1024);
+        transfer.add_uploaded(1024);
+
+        let mut stages = BTreeMap::new();
+        stages.insert(
+            "add_images".to_string(),
+            StageTiming {
+                calls: 3,
+                retries: 1,
+                errors: 0,
+                duration_ms: 120,
+                max_duration_ms: 60,
+            },
+        );
+
+        let images = RunReport::new(
+            &Ok(()),
+            Duration::from_millis(150),
+            Some(3),
+            &transfer,
+            stages,
+        );
+        Report::write(&dir, 1724122, RunKind::Images, images.clone())?;
+
+        let r: anyhow::Result<()> = Err(SyncError::UpstreamNotFound {
+            url: "https://ltn.hitomi.la/galleries/1724122.js".to_string(),
+        }
+        .into());
+        let book = RunReport::new(
+            &r,
+            Duration::from_millis(30),
+            None,
+            &Transfer::default(),
+            BTreeMap::new(),
+        );
+        Report::write(&dir, 1724122, RunKind::Book, book)?;
+
+        let report = Report::load(&dir, 1724122)?.unwrap();
+
+        assert_eq!(Some(images), report.images);
+        assert_eq!(3072, report.images.as_ref().unwrap().downloaded_bytes);
+
+        let book = report.book.unwrap();
+
+        assert!(!book.is_ok());
+        assert_eq!(Some("upstream_not_found"), book.error_kind.as_deref());
+
+        fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
@@ -1,28 +1,160 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
 use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use log::{error, info};
+use log::kv::ToValue;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+
+use crate::config::RetryConfig;
+use crate::error::{self, ErrorKind};
+use crate::journal::Journal;
+use crate::metrics::{Metrics, Outcome};
 
 pub struct StageUpdater<ID>
 where
-    ID: Display,
+    ID: Display + ToValue,
 {
     id: ID,
+    /// Successful calls of stages, errors are counted by `timings`
     inner: Mutex<HashMap<u8, usize>>,
+    retry: RetryConfig,
+    /// Records fulfilled stages of `u32` ids
+    journal: Option<(Arc<Journal>, u32)>,
+    metrics: Option<Arc<Metrics>>,
+    timings: Mutex<HashMap<u8, StageTiming>>,
+    /// `language` of galleryinfo, known after parsing
+    language: Mutex<Option<String>>,
+}
+
+/// # Stage Timing
+/// Calls of a stage, `duration_ms` includes retries and backoff
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StageTiming {
+    pub calls: usize,
+    pub retries: usize,
+    pub errors: usize,
+    pub duration_ms: u64,
+    pub max_duration_ms: u64,
+}
+
+/// # Event
+/// Fields of a stage log, see [`crate::logger`]
+struct Event<'a> {
+    stage: &'a Stage,
+    state: &'static str,
+    progress: Option<f64>,
+    error_kind: Option<ErrorKind>,
+    duration: Option<Duration>,
+}
+
+impl<'a> Event<'a> {
+    fn new(stage: &'a Stage, state: &'static str) -> Self {
+        Self {
+            stage,
+            state,
+            progress: None,
+            error_kind: None,
+            duration: None,
+        }
+    }
+
+    fn with_progress(mut self, progress: f64) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    fn with_error_kind(mut self, error_kind: ErrorKind) -> Self {
+        self.error_kind = Some(error_kind);
+        self
+    }
+
+    fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
 }
 
 impl<ID> StageUpdater<ID>
 where
-    ID: Display,
+    ID: Display + ToValue,
 {
     pub fn new(id: ID) -> Self {
         Self {
             id,
             inner: Mutex::new(HashMap::new()),
+            retry: RetryConfig::default(),
+            journal: None,
+            metrics: None,
+            timings: Mutex::new(HashMap::new()),
+            language: Mutex::new(None),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn set_language(&self, language: Option<String>) {
+        *self.language.lock().unwrap() = language;
+    }
+
+    /// `args` of text logs, and the fields of `event` of JSON logs
+    fn log(&self, level: Level, event: Event<'_>, args: fmt::Arguments<'_>) {
+        let language = self.language.lock().unwrap();
+
+        log!(
+            level,
+            gallery_id = self.id,
+            stage = event.stage.as_str(),
+            state = event.state,
+            progress = event.progress,
+            error_kind = event.error_kind.map(|kind| kind.as_str()),
+            duration_ms = event.duration.map(|duration| duration.as_millis() as u64),
+            language = language.as_deref();
+            "{}",
+            args
+        );
+    }
+
+    fn observe(&self, stage: &Stage, outcome: Outcome) {
+        if let Some(ref metrics) = self.metrics {
+            metrics.observe_stage(stage, outcome);
         }
     }
 
+    fn record(&self, stage: &Stage, retries: usize, is_err: bool, elapsed: Duration) {
+        let mut timings = self.timings.lock().unwrap();
+        let timing = timings.entry(stage.as_u8()).or_default();
+        let elapsed = elapsed.as_millis() as u64;
+
+        timing.calls += 1;
+        timing.retries += retries;
+        timing.errors += is_err as usize;
+        timing.duration_ms += elapsed;
+        timing.max_duration_ms = timing.max_duration_ms.max(elapsed);
+    }
+
+    /// Stage name => timing of the called stages
+    pub fn timings(&self) -> BTreeMap<String, StageTiming> {
+        self.timings
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(stage, timing)| (Stage::from(*stage).as_str().to_string(), timing.clone()))
+            .collect()
+    }
+
     pub fn update<T, F>(&self, stage: Stage, f: F) -> anyhow::Result<T>
     where
         F: Fn() -> StageR<T>,
@@ -32,57 +164,146 @@ where
 
             if !inner.contains_key(&stage.as_u8()) {
                 inner.insert(stage.as_u8(), 0);
-                info!("{}: {}: {}", self.id, stage, State::Ready);
+                self.log(
+                    Level::Info,
+                    Event::new(&stage, State::Ready.as_str()),
+                    format_args!("{}: {}: {}", self.id, stage, State::Ready),
+                );
             }
         }
 
-        let StageR(state, max_call_count, r) = f();
+        let retry_policy = self.retry.get(&stage);
+        let mut attempt = 1;
+        let started_at = Instant::now();
 
-        let current_call_count: usize = {
-            let mut inner = self.inner.lock().unwrap();
+        let StageR(state, max_call_count, r) = loop {
+            let stage_r = f();
 
-            let count = inner.get_mut(&stage.as_u8()).unwrap();
-            *count += 1;
-            *count
+            match stage_r.2 {
+                Err(ref err)
+                    if attempt < retry_policy.max_attempts
+                        && retry_policy.is_retryable(error::kind(err)) =>
+                {
+                    let delay = retry_policy.delay(attempt, rand::random());
+
+                    self.observe(&stage, Outcome::Retry);
+                    self.log(
+                        Level::Warn,
+                        Event::new(&stage, "retry")
+                            .with_error_kind(error::kind(err))
+                            .with_duration(started_at.elapsed()),
+                        format_args!(
+                            "{}: {}: Retry: {} / {} in {}ms: {}: {}",
+                            self.id,
+                            stage,
+                            attempt,
+                            retry_policy.max_attempts,
+                            delay.as_millis(),
+                            error::kind(err),
+                            err
+                        ),
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                _ => break stage_r,
+            }
         };
 
+        let elapsed = started_at.elapsed();
+
+        self.record(&stage, attempt - 1, r.is_err(), elapsed);
+
+        if let Some(ref metrics) = self.metrics {
+            metrics.observe_stage_duration(&stage, elapsed.as_secs_f64());
+        }
+
         match r {
             Ok(r) => {
+                self.observe(&stage, Outcome::Fulfilled);
+
+                let current_call_count: usize = {
+                    let mut inner = self.inner.lock().unwrap();
+
+                    let count = inner.get_mut(&stage.as_u8()).unwrap();
+                    *count += 1;
+                    *count
+                };
+
+                let is_fulfilled = match (&state, max_call_count) {
+                    (_, Some(max_call_count)) => max_call_count <= current_call_count,
+                    (State::Fulfilled, None) => true,
+                    _ => false,
+                };
+
+                if is_fulfilled {
+                    if let Some((ref journal, id)) = self.journal {
+                        journal.fulfill(id, &stage)?;
+                    }
+                }
+
                 if let Some(max_call_count) = max_call_count {
                     let progress = (current_call_count as f64 / max_call_count as f64) * 100.0;
                     // debug!("{} / {} = {}", current_call_count, max_call_count, progress);
-                    if 100.0 <= progress {
-                        info!(
-                            "{}: {}: {}: {} / {} => {}%",
-                            self.id,
-                            stage,
-                            State::Fulfilled,
-                            current_call_count,
-                            max_call_count,
-                            progress
-                        );
+                    let state = if 100.0 <= progress {
+                        State::Fulfilled
                     } else {
-                        info!(
+                        state
+                    };
+
+                    self.log(
+                        Level::Info,
+                        Event::new(&stage, state.as_str())
+                            .with_progress(progress)
+                            .with_duration(elapsed),
+                        format_args!(
                             "{}: {}: {}: {} / {} => {}%",
                             self.id, stage, state, current_call_count, max_call_count, progress
-                        );
-                    }
+                        ),
+                    );
                 } else {
-                    info!("{}: {}: {}", self.id, stage, state);
+                    self.log(
+                        Level::Info,
+                        Event::new(&stage, state.as_str()).with_duration(elapsed),
+                        format_args!("{}: {}: {}", self.id, stage, state),
+                    );
                 }
                 Ok(r)
             }
             Err(err) => {
-                error!("{}: {}: Error: {:#?}", self.id, stage, err);
+                self.observe(&stage, Outcome::Error);
+
+                self.log(
+                    Level::Error,
+                    Event::new(&stage, "error")
+                        .with_error_kind(error::kind(&err))
+                        .with_duration(elapsed),
+                    format_args!(
+                        "{}: {}: Error: {} / {}: {}: {:#?}",
+                        self.id,
+                        stage,
+                        attempt,
+                        retry_policy.max_attempts,
+                        error::kind(&err),
+                        err
+                    ),
+                );
                 Err(err)
             }
         }
     }
 }
 
+impl StageUpdater<u32> {
+    pub fn with_journal(mut self, journal: Arc<Journal>) -> Self {
+        self.journal = Some((journal, self.id));
+        self
+    }
+}
+
 pub fn update<ID, T, F>(stage_updater: &StageUpdater<ID>, stage: Stage, f: F) -> anyhow::Result<T>
 where
-    ID: Display,
+    ID: Display + ToValue,
     F: Fn() -> StageR<T>,
 {
     stage_updater.update(stage, f)
@@ -98,6 +319,17 @@ pub enum State {
     Fulfilled,
 }
 
+impl State {
+    /// `state` of JSON logs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ready => "ready",
+            Self::Pending => "pending",
+            Self::Fulfilled => "fulfilled",
+        }
+    }
+}
+
 impl Display for State {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let r = match self {
@@ -155,7 +387,30 @@ impl From<u8> for Stage {
     }
 }
 
+impl FromStr for Stage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        (0..=5)
+            .map(Stage::from)
+            .find(|stage| stage.as_str() == s)
+            .ok_or_else(|| anyhow::Error::msg(format!("Unknown stage `{}`", s)))
+    }
+}
+
 impl Stage {
+    /// Same as keys of `RetryConfig`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ParseBook => "parse_book",
+            Self::ParseImages => "parse_images",
+            Self::AddThumbnail => "add_thumbnail",
+            Self::AddImages => "add_images",
+            Self::AddImageList => "add_image_list",
+            Self::AddBook => "add_book",
+        }
+    }
+
     pub fn as_u8(&self) -> u8 {
         match self {
             Self::ParseBook => 0,
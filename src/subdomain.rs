@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow;
+use log::{debug, warn};
+
+use crate::error::SyncError;
+use crate::http::Request;
+use crate::upstream::Upstream;
+
+/// gg.js changes routinely
+const DEFAULT_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// # gg.js
+/// Routing table of image subdomains of hitomi
+///
+/// ```js
+/// gg = {
+/// m: function(g) {
+/// var o = 0;
+/// switch (g) {
+/// case 1234:
+/// case 2345:
+/// o = 1; break;
+/// }
+/// return o;
+/// },
+/// s: function(h) { var m = /(..)(.)$/.exec(h); return parseInt(m[2]+m[1], 16).toString(10); },
+/// b: '1650348721/'
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct GgTable {
+    default: u32,
+    cases: HashMap<u32, u32>,
+    b: String,
+}
+
+impl GgTable {
+    /// `None` if `var o` or `b` of gg.js are not found
+    pub fn parse(js: &str) -> Option<Self> {
+        let mut default = None;
+        let mut cases = HashMap::new();
+        let mut pending: Vec<u32> = vec![];
+
+        // `case 1234:`, `o = 1; break;`, `var o = 0;`
+        for statement in js.split(&['\n', ';', ':'][..]) {
+            let statement = statement.trim();
+
+            if let Some(g) = statement.strip_prefix("case ") {
+                pending.push(g.trim().parse().ok()?);
+            } else if let Some(o) = statement.strip_prefix("var o = ") {
+                default = Some(o.trim().parse().ok()?);
+            } else if let Some(o) = statement.strip_prefix("o = ") {
+                let o: u32 = o.trim().parse().ok()?;
+                cases.extend(pending.drain(..).map(|g| (g, o)));
+            }
+        }
+
+        // b: '1650348721/'
+        let b = js.find("b: '").map(|i| &js[i + 4..])?;
+        let b = &b[..b.find('\'')?];
+
+        Some(Self {
+            default: default?,
+            cases,
+            b: b.to_string(),
+        })
+    }
+
+    pub fn m(&self, g: u32) -> u32 {
+        self.cases.get(&g).copied().unwrap_or(self.default)
+    }
+
+    /// Path prefix of images
+    pub fn b(&self) -> &str {
+        &self.b
+    }
+
+    /// `gg.s()`, the last 3 characters of hash, `...abc` => `0xcab`
+    pub fn s(hash: &str) -> Option<u32> {
+        let postfix = hash
+            .get(hash.len().checked_sub(3)?..)
+            .filter(|x| x.is_ascii())?;
+
+        u32::from_str_radix(&format!("{}{}", &postfix[2..], &postfix[..2]), 16).ok()
+    }
+}
+
+struct Cached {
+    table: Option<Arc<GgTable>>,
+    fetched_at: Instant,
+}
+
+/// # Subdomain Resolver
+/// Fetches and caches gg.js, `None` means the legacy rule of `File::url`
+pub struct SubdomainResolver {
+    cached: Mutex<Option<Cached>>,
+    ttl: Duration,
+}
+
+impl Default for SubdomainResolver {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL)
+    }
+}
+
+impl SubdomainResolver {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            cached: Mutex::new(None),
+            ttl,
+        }
+    }
+
+    /// Cached table, fetches gg.js if expired or invalidated
+    pub fn table(&self, upstream: &Upstream) -> Option<Arc<GgTable>> {
+        let mut cached = self.cached.lock().unwrap();
+
+        if let Some(ref cached) = *cached {
+            if cached.fetched_at.elapsed() < self.ttl {
+                return cached.table.clone();
+            }
+        }
+
+        let table = match fetch(upstream) {
+            Ok(table) => {
+                debug!("gg.js: b = {}, {} cases", table.b, table.cases.len());
+                Some(Arc::new(table))
+            }
+            Err(err) => {
+                warn!("gg.js: Fallback to legacy subdomains: {}", err);
+                None
+            }
+        };
+
+        *cached = Some(Cached {
+            table: table.clone(),
+            fetched_at: Instant::now(),
+        });
+
+        table
+    }
+
+    /// Drops the cache if it is still `used`, which failed downloads,
+    /// so parallel failures of the same table fetch gg.js once
+    pub fn invalidate(&self, used: Option<&Arc<GgTable>>) {
+        let mut cached = self.cached.lock().unwrap();
+
+        let is_used = match *cached {
+            Some(ref cached) => match (cached.table.as_ref(), used) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            },
+            None => false,
+        };
+
+        if is_used {
+            debug!("gg.js: Invalidated");
+            *cached = None;
+        }
+    }
+}
+
+fn fetch(upstream: &Upstream) -> anyhow::Result<GgTable> {
+    let url = upstream.endpoints.ltn("gg.js");
+    let response = upstream.send(Request::get(&url))?;
+
+    if !response.is_success() {
+        return Err(SyncError::from_status(url, response.status).into());
+    }
+
+    let js = response.text()?;
+
+    GgTable::parse(&js)
+        .ok_or_else(|| anyhow::Error::msg(format!("Can't parse `var o` or `b` of {}", url)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{GgTable, SubdomainResolver};
+    use crate::parser::test_upstream;
+
+    #[test]
+    fn parse_gg_js() -> anyhow::Result<()> {
+        let upstream = test_upstream();
+
+        let table = SubdomainResolver::default()
+            .table(&upstream)
+            .ok_or_else(|| anyhow::Error::msg("gg.js"))?;
+
+        assert_eq!("1650348721/", table.b());
+        assert_eq!(1, table.m(1234));
+        assert_eq!(1, table.m(2345));
+        assert_eq!(0, table.m(3456));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_gg_js_invalid() {
+        assert_eq!(None, GgTable::parse("gg = {};"));
+        assert_eq!(None, GgTable::parse("var o = 0; case abc: o = 1;"));
+    }
+
+    #[test]
+    fn s_of_hash() {
+        assert_eq!(Some(0xcab), GgTable::s("0123abc"));
+        assert_eq!(None, GgTable::s("ab"));
+        assert_eq!(None, GgTable::s("xyz"));
+    }
+
+    #[test]
+    fn invalidate_used_table() -> anyhow::Result<()> {
+        let upstream = test_upstream();
+        let resolver = SubdomainResolver::new(Duration::from_secs(60));
+
+        let a = resolver.table(&upstream);
+        let b = resolver.table(&upstream);
+
+        assert!(Arc::ptr_eq(a.as_ref().unwrap(), b.as_ref().unwrap()));
+
+        resolver.invalidate(a.as_ref());
+        let c = resolver.table(&upstream);
+
+        assert!(!Arc::ptr_eq(a.as_ref().unwrap(), c.as_ref().unwrap()));
+
+        // already refreshed by another download
+        resolver.invalidate(a.as_ref());
+        let d = resolver.table(&upstream);
+
+        assert!(Arc::ptr_eq(c.as_ref().unwrap(), d.as_ref().unwrap()));
+
+        Ok(())
+    }
+}
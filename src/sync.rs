@@ -0,0 +1,789 @@
+use std::fs;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow;
+use bytes::Bytes;
+use fp_core::lens::Lens;
+use log::{debug, info, trace, warn};
+use madome_client::auth::Token;
+use madome_client::book::{Book, MetadataBook};
+use madome_client::{AuthClient, BookClient, FileClient};
+use rayon::prelude::*;
+
+use crate::cli::{Paging, Sources};
+use crate::config::Config;
+use crate::cursor::{Cursors, HighWaterMarks};
+use crate::dedup::HashIndex;
+use crate::drift::LayoutDrift;
+use crate::error::{self, ErrorKind, SyncError};
+use crate::journal::Journal;
+use crate::metrics::Metrics;
+use crate::parser::{self, NozomiSource, Parser};
+use crate::progress::PageProgress;
+use crate::report::{Report, RunKind, RunReport, Transfer};
+use crate::stage::{self, Stage, StageR, StageUpdater, State};
+use crate::thumbnail::{self, BIGTN_WIDTH};
+use crate::transcode::Savings;
+use crate::upstream::Upstream;
+use crate::utils::{get_ext, IntoResultVec, TextStore};
+
+pub struct TokenLens;
+
+impl Lens<Token, String> for TokenLens {
+    fn get(s: &Token) -> Option<&String> {
+        Some(&s.token)
+    }
+
+    fn set(a: String, _: &Token) -> Token {
+        Token { token: a }
+    }
+}
+
+pub struct TokenManager;
+
+impl TokenManager {
+    pub fn refresh(
+        auth_client: &AuthClient,
+        token: Token,
+        token_path: &str,
+    ) -> anyhow::Result<Token> {
+        let old_token = TokenLens::get(&token).unwrap();
+        let new_token =
+            auth_client
+                .refresh_token(old_token)
+                .map_err(|err| SyncError::MadomeUnauthorized {
+                    cause: err.to_string(),
+                })?;
+
+        fs::write(token_path, &new_token)?;
+
+        let new_token = TokenLens::set(new_token, &token);
+
+        Ok(new_token)
+    }
+
+    /// Token of `token_path`
+    pub fn load(token_path: &str) -> anyhow::Result<Token> {
+        let token = fs::read(token_path)?;
+        let token = String::from_utf8(token)?.trim().to_string();
+
+        Ok(Token { token })
+    }
+}
+
+/// # Context
+/// Shared state of a synchronize run
+pub struct Context {
+    pub config: Config,
+    pub upstream: Arc<Upstream>,
+    pub book_client: BookClient,
+    /// Refreshed before every cycle of long running commands
+    token: RwLock<Token>,
+    pub fail_store: Mutex<TextStore<u32>>,
+    pub layout_drift: Mutex<LayoutDrift>,
+    pub hash_index: HashIndex,
+    pub journal: Arc<Journal>,
+    pub metrics: Arc<Metrics>,
+    pub image_pool: rayon::ThreadPool,
+}
+
+impl Context {
+    /// Loads fail_store, hash_index and journal of the paths of `config`
+    pub fn new(config: Config, upstream: Arc<Upstream>, token: Token) -> anyhow::Result<Self> {
+        let book_client = BookClient::new(&config.madome_url);
+        let fail_store = TextStore::from_file(&config.fail_store_path)?;
+        let hash_index = HashIndex::load(&config.hash_index_path)?;
+        let journal = Arc::new(Journal::load(&config.journal_path)?);
+        let metrics = Arc::new(Metrics::new()?);
+        metrics.set_fail_store_ids(fail_store.iter().count());
+        let image_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(config.concurrency.images)
+            .build()?;
+
+        Ok(Self {
+            config,
+            upstream,
+            book_client,
+            token: RwLock::new(token),
+            fail_store: Mutex::new(fail_store),
+            layout_drift: Mutex::new(LayoutDrift::default()),
+            hash_index,
+            journal,
+            metrics,
+            image_pool,
+        })
+    }
+
+    pub fn token(&self) -> String {
+        TokenLens::get(&self.token.read().unwrap()).unwrap().clone()
+    }
+
+    /// Re-reads `token_path` and refreshes the token of it
+    pub fn refresh_token(&self) -> anyhow::Result<()> {
+        let auth_client = AuthClient::new(&self.config.madome_url);
+        let token_path = &self.config.token_path;
+        let token =
+            TokenManager::refresh(&auth_client, TokenManager::load(token_path)?, token_path)?;
+
+        *self.token.write().unwrap() = token;
+
+        Ok(())
+    }
+
+    pub fn synchronize_fail_store(&self) -> anyhow::Result<()> {
+        let fail_store = self.fail_store.lock().unwrap();
+
+        fail_store.synchronize(&self.config.fail_store_path)?;
+        self.metrics.set_fail_store_ids(fail_store.iter().count());
+
+        Ok(())
+    }
+
+    /// Logs the fields of hitomi failed since the last report
+    pub fn report_layout_drift(&self) {
+        let mut layout_drift = self.layout_drift.lock().unwrap();
+
+        for line in layout_drift.summary() {
+            warn!("Layout drift: {}", line);
+        }
+
+        layout_drift.clear();
+    }
+}
+
+fn parse_ids(
+    context: &Context,
+    source: &NozomiSource,
+    page: usize,
+    per_page: usize,
+) -> anyhow::Result<Vec<u32>> {
+    trace!("parse_ids({}, {}, {})", source, page, per_page);
+    context
+        .metrics
+        .set_nozomi_page(&source.to_string(), source.language(), page);
+
+    parser::Nozomi::from_source(source.clone(), page, per_page)
+        .with_upstream(context.upstream.clone())
+        .request()?
+        .parse()
+}
+
+/// Index of each language, or the specified sources of each language
+fn nozomi_sources(context: &Context, sources: &Sources) -> anyhow::Result<Vec<NozomiSource>> {
+    let languages = &context.config.languages;
+
+    if sources.specs.is_empty() {
+        return Ok(languages.iter().map(NozomiSource::index).collect());
+    }
+
+    let mut r = vec![];
+
+    for spec in &sources.specs {
+        for language in languages {
+            r.push(NozomiSource::parse(spec, language)?);
+        }
+    }
+
+    Ok(r)
+}
+
+/// (images, language)
+fn parse_images(context: &Context, id: u32) -> anyhow::Result<(Vec<parser::File>, Option<String>)> {
+    trace!("parse_image({})", id);
+    let image = parser::Image::new(id)
+        .with_upstream(context.upstream.clone())
+        .request()?;
+    let images = image.parse()?;
+
+    if images.is_empty() {
+        return Err(SyncError::layout_changed(id, "files", "galleryinfo.files").into());
+    }
+
+    Ok((images, image.language()?))
+}
+
+/// Uploads to file repository of Madome, failures are `SyncError::UploadFailed` except unauthorized
+fn upload(
+    context: &Context,
+    transfer: &Transfer,
+    url_path: &str,
+    body: impl AsRef<[u8]>,
+) -> anyhow::Result<()> {
+    let file_client = FileClient::new(&context.config.file_repository_url);
+    let len = body.as_ref().len();
+
+    file_client
+        .upload(&context.token(), url_path, body)
+        .map_err(|err| {
+            if error::is_fatal(&err) {
+                return err;
+            }
+
+            SyncError::UploadFailed {
+                url_path: url_path.to_string(),
+                cause: err.to_string(),
+            }
+            .into()
+        })?;
+
+    context.metrics.observe_uploaded(len);
+    transfer.add_uploaded(len);
+
+    Ok(())
+}
+
+fn add_image(
+    context: &Context,
+    id: u32,
+    page: usize,
+    image: &parser::File,
+    savings: &Savings,
+    transfer: &Transfer,
+) -> anyhow::Result<String> {
+    // same image of another gallery
+    if let Some(url_path) = context.hash_index.get(&image.hash) {
+        debug!("{}: Page {} is uploaded as {}", id, page, url_path);
+        return Ok(url_path);
+    }
+
+    image
+        .download(&context.upstream, id, false, &context.config.images.formats)
+        .and_then(|(origin_url, buf)| {
+            context.metrics.observe_downloaded(buf.len());
+            transfer.add_downloaded(buf.len());
+
+            let ext = get_ext(&origin_url).unwrap_or("jpg");
+
+            let (buf, ext) = match context.config.transcode.transcoder() {
+                Some(transcoder) => match transcoder.transcode(&buf) {
+                    Ok((transcoded, transcoded_ext)) if transcoded.len() < buf.len() => {
+                        savings.add(buf.len(), transcoded.len());
+                        (Bytes::from(transcoded), transcoded_ext)
+                    }
+                    Ok((transcoded, _)) => {
+                        debug!(
+                            "{}: Upload page {} as downloaded, transcoded is not smaller: {} => {} bytes",
+                            id,
+                            page,
+                            buf.len(),
+                            transcoded.len()
+                        );
+                        savings.add(buf.len(), buf.len());
+                        (buf, ext)
+                    }
+                    Err(err) => {
+                        warn!("{}: Upload page {} as downloaded: {}", id, page, err);
+                        (buf, ext)
+                    }
+                },
+                None => (buf, ext),
+            };
+
+            let filename = format!("{}.{}", page, ext);
+            let url_path = format!("image/library/{}/{}", id, filename);
+
+            upload(context, transfer, &url_path, buf)?;
+            context.hash_index.insert(&image.hash, &url_path)?;
+
+            Ok(url_path)
+        })
+}
+
+/// bigtn of hitomi, or generated from the first page if bigtn is not found
+fn add_thumbnail(
+    context: &Context,
+    id: u32,
+    image: &parser::File,
+    transfer: &Transfer,
+) -> anyhow::Result<()> {
+    let bigtn = match image.download(&context.upstream, id, true, &[]) {
+        Ok((origin_url, buf)) => {
+            context.metrics.observe_downloaded(buf.len());
+            transfer.add_downloaded(buf.len());
+            Some((origin_url, buf))
+        }
+        Err(err) if error::kind(&err) == ErrorKind::UpstreamNotFound => {
+            warn!("{}: Generate thumbnail from the first page: {}", id, err);
+            None
+        }
+        Err(err) => return Err(err),
+    };
+
+    let download_page = || {
+        image
+            .download(&context.upstream, id, false, &[])
+            .map(|(_, buf)| {
+                context.metrics.observe_downloaded(buf.len());
+                transfer.add_downloaded(buf.len());
+                buf
+            })
+    };
+
+    let page = match bigtn {
+        Some((origin_url, buf)) => {
+            let ext = get_ext(&origin_url).unwrap_or("jpg");
+            let url_path = format!("image/library/{}/thumbnail.{}", id, ext);
+            upload(context, transfer, &url_path, buf)?;
+            None
+        }
+        None => {
+            let page = download_page()?;
+            let url_path = format!("image/library/{}/thumbnail.jpg", id);
+            upload(
+                context,
+                transfer,
+                &url_path,
+                thumbnail::generate(&page, BIGTN_WIDTH)?,
+            )?;
+            Some(page)
+        }
+    };
+
+    let sizes = &context.config.images.thumbnail_sizes;
+
+    if !sizes.is_empty() {
+        let page = match page {
+            Some(page) => page,
+            None => download_page()?,
+        };
+
+        for size in sizes {
+            let url_path = format!("image/library/{}/{}", id, size.filename());
+            upload(
+                context,
+                transfer,
+                &url_path,
+                thumbnail::generate(&page, size.width())?,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn add_image_list_txt(
+    context: &Context,
+    id: u32,
+    image_list: &[String],
+    transfer: &Transfer,
+) -> anyhow::Result<()> {
+    let file_repository_url = &context.config.file_repository_url;
+
+    let image_list_txt = image_list.iter().fold(String::new(), |mut acc, url_path| {
+        acc.push_str(&format!("{}/{}", file_repository_url, url_path));
+        acc.push('\n');
+        acc
+    });
+
+    upload(
+        context,
+        transfer,
+        &format!("image/library/{}/image_list.txt", id),
+        image_list_txt.trim(),
+    )
+}
+
+/// (book, language) of `galleries/{id}.js`, falls back to `Gallery` and `GalleryBlock` on layout changes
+fn parse_book(context: &Context, id: u32) -> anyhow::Result<(Book, Option<String>)> {
+    let gallery_info = parser::GalleryInfo::new(id)
+        .with_upstream(context.upstream.clone())
+        .request()?;
+
+    let page = gallery_info.files()?.len();
+
+    if page == 0 {
+        return Err(SyncError::layout_changed(id, "files", "galleryinfo.files").into());
+    }
+
+    let metadata_book = match gallery_info.parse() {
+        Ok(metadata_book) => metadata_book,
+        Err(err) if error::kind(&err) == ErrorKind::ParseLayoutChanged => {
+            warn!("{}: Fallback to HTML: {}", id, err);
+            context.layout_drift.lock().unwrap().record(&err);
+
+            parse_book_html(context, id)?
+        }
+        Err(err) => return Err(err),
+    };
+
+    let book = Book {
+        page_count: page,
+        ..Book::from(metadata_book)
+    };
+
+    // only for logs, galleryinfo may be changed
+    let language = gallery_info.language().ok().flatten();
+
+    Ok((book, language))
+}
+
+fn parse_book_html(context: &Context, id: u32) -> anyhow::Result<MetadataBook> {
+    let gallery_data = parser::Gallery::new(id)
+        .with_upstream(context.upstream.clone())
+        .request()?
+        .parse()?;
+    let mut gallery_block_data = parser::GalleryBlock::new(id)
+        .with_upstream(context.upstream.clone())
+        .request()?
+        .parse()?;
+
+    gallery_block_data.groups = gallery_data.groups;
+    gallery_block_data.characters = gallery_data.characters;
+
+    Ok(gallery_block_data)
+}
+
+fn add_book(context: &Context, book: &Book) -> anyhow::Result<()> {
+    let book_client = BookClient::new(&context.config.madome_url);
+
+    book_client.create_book(&context.token(), book)
+}
+
+/// Images or book of `id`, fail_store and the journal are kept by `synchronize_ids`
+pub fn sync(context: &Context, id: u32, sync_images: bool, sync_info: bool) -> anyhow::Result<()> {
+    let started_at = Instant::now();
+    let transfer = Transfer::default();
+    let stage_updater = StageUpdater::new(id)
+        .with_retry(context.config.retry.clone())
+        .with_journal(context.journal.clone())
+        .with_metrics(context.metrics.clone());
+
+    let parse_images = |id: u32| {
+        stage::update(&stage_updater, Stage::ParseImages, || {
+            let r = parse_images(context, id).map(|(images, language)| {
+                stage_updater.set_language(language);
+                images
+            });
+            StageR(State::Fulfilled, None, r)
+        })
+    };
+
+    let add_thumbnail = |id: u32, image: &parser::File| {
+        stage::update(&stage_updater, Stage::AddThumbnail, || {
+            let r = add_thumbnail(context, id, image, &transfer);
+            StageR(State::Fulfilled, None, r)
+        })
+    };
+
+    let savings = Savings::default();
+
+    let add_image = |id: u32, current_page: usize, max_page: usize, image: &parser::File| {
+        stage::update(&stage_updater, Stage::AddImages, || {
+            let r = add_image(context, id, current_page, image, &savings, &transfer);
+            StageR(State::Pending, Some(max_page), r)
+        })
+    };
+
+    let add_image_list_txt = |id: u32, image_list: &[String]| {
+        stage::update(&stage_updater, Stage::AddImageList, || {
+            let r = add_image_list_txt(context, id, image_list, &transfer);
+            StageR(State::Fulfilled, None, r)
+        })
+    };
+
+    let parse_book = |id: u32| {
+        stage::update(&stage_updater, Stage::ParseBook, || {
+            let r = parse_book(context, id).map(|(book, language)| {
+                stage_updater.set_language(language);
+                book
+            });
+            StageR(State::Fulfilled, None, r)
+        })
+    };
+
+    let add_book = |book: Book| {
+        stage::update(&stage_updater, Stage::AddBook, || {
+            let r = add_book(context, &book);
+            StageR(State::Fulfilled, None, r)
+        })
+    };
+
+    // fulfilled before crashed
+    if sync_info && context.journal.is_fulfilled(id, &Stage::AddBook) {
+        debug!("{}: Resume after {}", id, Stage::AddBook);
+        return Ok(());
+    }
+
+    if sync_images && context.journal.is_fulfilled(id, &Stage::AddImageList) {
+        debug!("{}: Resume after {}", id, Stage::AddImageList);
+        return Ok(());
+    }
+
+    let mut page_count = None;
+
+    let (kind, r) = if sync_info {
+        let r = parse_book(id).and_then(|book| {
+            page_count = Some(book.page_count);
+            add_book(book)
+        });
+
+        (RunKind::Book, r)
+    } else if sync_images {
+        let r = parse_images(id).and_then(|images| {
+            page_count = Some(images.len());
+
+            let progress = PageProgress::load(&context.config.progress_dir, id, images.len())?;
+            let progress = Mutex::new(progress);
+
+            if !progress.lock().unwrap().has_thumbnail() {
+                add_thumbnail(id, &images[0])?;
+
+                let mut progress = progress.lock().unwrap();
+                progress.set_thumbnail();
+                progress.synchronize()?;
+            }
+
+            // add the missing pages, and keep uploaded pages even if a page failed
+            let missing_pages = (1..=images.len())
+                .filter(|page| !progress.lock().unwrap().has_page(*page))
+                .collect::<Vec<_>>();
+            let missing_len = missing_pages.len();
+
+            let uploaded = context.image_pool.install(|| {
+                missing_pages
+                    .into_par_iter()
+                    .map(|page| {
+                        let url_path = add_image(id, page, missing_len, &images[page - 1])?;
+
+                        let mut progress = progress.lock().unwrap();
+                        progress.set_page(page, url_path);
+                        progress.synchronize_batch()
+                    })
+                    .collect::<Vec<_>>()
+                    .into_result_vec()
+            });
+
+            if let Err(err) = uploaded {
+                progress.lock().unwrap().synchronize()?;
+                return Err(err);
+            }
+
+            let progress = progress.into_inner().unwrap();
+            let image_list = progress
+                .image_list()
+                .ok_or_else(|| anyhow::Error::msg(format!("{}: Missing pages in progress", id)))?;
+
+            add_image_list_txt(id, &image_list)?;
+
+            if savings.pages() > 0 {
+                info!("{}: Transcoded {}", id, savings);
+            }
+
+            progress.remove()
+        });
+
+        (RunKind::Images, r)
+    } else {
+        return Ok(());
+    };
+
+    let run = RunReport::new(
+        &r,
+        started_at.elapsed(),
+        page_count,
+        &transfer,
+        stage_updater.timings(),
+    );
+
+    if let Err(err) = Report::write(&context.config.report_dir, id, kind, run) {
+        warn!("{}: Report: {}", id, err);
+    }
+
+    r
+}
+
+/// Returns IDs that were not synchronized with Madome yet,
+/// and stops at fatal errors such as `SyncError::MadomeUnauthorized`
+pub fn synchronize_ids(context: &Context, ids: Vec<u32>) -> anyhow::Result<Vec<u32>> {
+    let Context { book_client, .. } = context;
+
+    let r = ids
+        .into_par_iter()
+        .map(|id| {
+            let _in_flight = context.metrics.in_flight();
+
+            let already_images = book_client.get_image_list(&context.token(), id).is_ok();
+
+            let already_book_info = book_client
+                .get_book_by_id(&context.token(), id as i32)
+                .is_ok();
+
+            if already_images && already_book_info {
+                debug!("{}: Already has book in Madome", id);
+                context.fail_store.lock().unwrap().remove(&id);
+                context.journal.finish(id)?;
+                return Ok(None);
+            }
+
+            context.journal.start(id)?;
+
+            let images = match already_images {
+                true => Ok(()),
+                false => sync(context, id, true, false),
+            };
+
+            let book_info = match already_book_info {
+                true => Ok(()),
+                false => sync(context, id, false, true),
+            };
+
+            if images.is_err() || book_info.is_err() {
+                context.fail_store.lock().unwrap().add(id);
+                context.journal.fail(id)?;
+            } else {
+                context.fail_store.lock().unwrap().remove(&id);
+                context.journal.finish(id)?;
+            }
+
+            for r in [images, book_info] {
+                match r {
+                    Err(err) if error::is_fatal(&err) => return Err(err),
+                    Err(err) => {
+                        context.layout_drift.lock().unwrap().record(&err);
+                    }
+                    Ok(_) => {}
+                }
+            }
+
+            Ok(Some(id))
+        })
+        .collect::<Vec<_>>()
+        .into_result_vec()?;
+
+    Ok(r.into_iter().flatten().collect())
+}
+
+/// `SyncError::NoNewIds` if every IDs were already synchronized with Madome
+fn synchronize_new_ids(context: &Context, ids: Vec<u32>) -> anyhow::Result<Vec<u32>> {
+    let synchronized_ids = synchronize_ids(context, ids)?;
+
+    if synchronized_ids.is_empty() {
+        return Err(SyncError::NoNewIds.into());
+    }
+
+    Ok(synchronized_ids)
+}
+
+pub fn sync_latest(context: &Context, paging: Paging, sources: Sources) -> anyhow::Result<()> {
+    let per_page = context.config.sync.per_page;
+    let latency = context.config.sync.latency;
+    let cursor_path = &context.config.cursor_path;
+
+    let mut marks = HighWaterMarks::from_file(cursor_path)?;
+    let mut cursors =
+        Cursors::new(nozomi_sources(context, &sources)?, paging.page).with_marks(&marks);
+
+    loop {
+        for cursor in cursors.active() {
+            let ids = parse_ids(context, &cursor.source, cursor.page, per_page)?;
+            let (ids, reached_mark) = cursor.newer_ids(ids);
+
+            let no_new_ids = match synchronize_new_ids(context, ids) {
+                Ok(_) => false,
+                Err(err) if error::kind(&err) == ErrorKind::NoNewIds => true,
+                Err(err) => return Err(err),
+            };
+
+            // without high-water mark, the end is the page already synchronized
+            let is_end = match cursor.mark() {
+                Some(_) => reached_mark,
+                None => no_new_ids,
+            };
+
+            if is_end {
+                info!(
+                    "The end synchronize of {}, Page = {}",
+                    cursor.source, cursor.page
+                );
+                cursor.finish();
+            } else {
+                cursor.advance();
+            }
+        }
+
+        context.synchronize_fail_store()?;
+
+        if cursors.is_done() {
+            marks.update(&cursors);
+            marks.synchronize(cursor_path)?;
+            context.report_layout_drift();
+
+            info!("Waiting next synchronize cycle.");
+            thread::sleep(Duration::from_secs(latency));
+            cursors.reset();
+            context.refresh_token()?;
+        }
+    }
+}
+
+pub fn backfill(context: &Context, paging: Paging, sources: Sources) -> anyhow::Result<()> {
+    let per_page = context.config.sync.per_page;
+    let mut cursors = Cursors::new(nozomi_sources(context, &sources)?, paging.page);
+
+    while !cursors.is_done() {
+        context.refresh_token()?;
+
+        for cursor in cursors.active() {
+            let ids = parse_ids(context, &cursor.source, cursor.page, per_page)?;
+
+            if cursor.is_end(&ids) {
+                info!(
+                    "The end backfill of {}, Page = {}",
+                    cursor.source, cursor.page
+                );
+                continue;
+            }
+
+            synchronize_ids(context, ids)?;
+
+            cursor.advance();
+        }
+
+        context.synchronize_fail_store()?;
+    }
+
+    context.report_layout_drift();
+
+    Ok(())
+}
+
+/// Synchronizes the incomplete ids of the journal, from their last fulfilled stages
+pub fn resume(context: &Context) -> anyhow::Result<()> {
+    let ids = context.journal.incomplete();
+
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    info!("Resume synchronize {} incomplete ids", ids.len());
+
+    synchronize_ids(context, ids)?;
+
+    context.synchronize_fail_store()
+}
+
+pub fn retry_fail(context: &Context) -> anyhow::Result<()> {
+    let ids = context
+        .fail_store
+        .lock()
+        .unwrap()
+        .iter()
+        .copied()
+        .collect::<Vec<_>>();
+
+    info!("Retry synchronize {} failed ids", ids.len());
+
+    synchronize_ids(context, ids)?;
+
+    context.report_layout_drift();
+    context.synchronize_fail_store()
+}
+
+pub fn sync_id(context: &Context, ids: Vec<u32>) -> anyhow::Result<()> {
+    synchronize_ids(context, ids)?;
+
+    context.report_layout_drift();
+    context.synchronize_fail_store()
+}
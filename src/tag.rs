@@ -0,0 +1,213 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use anyhow;
+
+/// # Namespace
+/// Namespace of a tag of hitomi
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Namespace {
+    Female,
+    Male,
+    /// Tags without sign, `full color`
+    Misc,
+}
+
+impl Namespace {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Female => "female",
+            Self::Male => "male",
+            Self::Misc => "misc",
+        }
+    }
+
+    /// Suffix of tags of Madome
+    fn sign(&self) -> Option<&'static str> {
+        match self {
+            Self::Female => Some("♀"),
+            Self::Male => Some("♂"),
+            Self::Misc => None,
+        }
+    }
+}
+
+impl Display for Namespace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// # Tag
+/// Tag of a gallery
+///
+/// Madome expects the sign of namespace as suffix, `Display` and `FromStr` are reversible
+///
+/// ```
+/// use madome_synchronizer::tag::{Namespace, Tag};
+///
+/// let tag = "sole female ♀".parse::<Tag>().unwrap();
+///
+/// assert_eq!(Namespace::Female, tag.namespace);
+/// assert_eq!("sole female", tag.name);
+/// assert_eq!("sole female ♀", tag.to_string());
+/// assert_eq!("female:sole female", tag.hitomi());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tag {
+    pub namespace: Namespace,
+    pub name: String,
+}
+
+impl Tag {
+    pub fn new(namespace: Namespace, name: impl Into<String>) -> Self {
+        Self {
+            namespace,
+            name: name.into().trim().to_string(),
+        }
+    }
+
+    /// `female` and `male` flags of `galleries/{id}.js`, female first if both
+    pub fn from_flags(name: impl Into<String>, female: bool, male: bool) -> Self {
+        let namespace = match (female, male) {
+            (true, _) => Namespace::Female,
+            (_, true) => Namespace::Male,
+            _ => Namespace::Misc,
+        };
+
+        Self::new(namespace, name)
+    }
+
+    /// Parses tags of hitomi url and nozomi, `female:sole female` or `full color`
+    pub fn from_hitomi(s: &str) -> Self {
+        let s = s.trim();
+
+        if let Some(name) = s.strip_prefix("female:") {
+            Self::new(Namespace::Female, name)
+        } else if let Some(name) = s.strip_prefix("male:") {
+            Self::new(Namespace::Male, name)
+        } else {
+            Self::new(Namespace::Misc, s)
+        }
+    }
+
+    /// Tag of hitomi url and nozomi, `female:sole female`
+    pub fn hitomi(&self) -> String {
+        match self.namespace {
+            Namespace::Misc => self.name.clone(),
+            namespace => format!("{}:{}", namespace, self.name),
+        }
+    }
+}
+
+/// Tag of Madome, `sole female ♀`
+impl Display for Tag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.namespace.sign() {
+            Some(sign) => write!(f, "{} {}", self.name, sign),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl FromStr for Tag {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        let tag = [Namespace::Female, Namespace::Male]
+            .iter()
+            .find_map(|namespace| {
+                let name = s.strip_suffix(namespace.sign()?)?;
+                Some(Self::new(*namespace, name))
+            })
+            .unwrap_or_else(|| Self::new(Namespace::Misc, s));
+
+        if tag.name.is_empty() {
+            return Err(anyhow::Error::msg(format!(
+                "Can't parse tag `{}`, name is empty",
+                s
+            )));
+        }
+
+        Ok(tag)
+    }
+}
+
+/// Tags of `MetadataBook`
+pub fn to_madome(tags: Vec<Tag>) -> Vec<String> {
+    tags.iter().map(Tag::to_string).collect()
+}
+
+/// Tags in `namespace`
+pub fn filter_namespace(tags: &[Tag], namespace: Namespace) -> impl Iterator<Item = &Tag> {
+    tags.iter().filter(move |tag| tag.namespace == namespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{filter_namespace, Namespace, Tag};
+
+    #[test]
+    fn parse_and_display() -> anyhow::Result<()> {
+        for raw in &["loli ♀", "sole male ♂", "incest", "x-ray"] {
+            let tag = raw.parse::<Tag>()?;
+
+            assert_eq!(*raw, tag.to_string());
+        }
+
+        assert_eq!(
+            Tag::new(Namespace::Male, "sole male"),
+            "sole male ♂".parse()?
+        );
+        assert_eq!(Tag::new(Namespace::Misc, "incest"), " incest ".parse()?);
+        assert!("♀".parse::<Tag>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_flags() {
+        assert_eq!(
+            Tag::new(Namespace::Female, "sole female"),
+            Tag::from_flags("sole female", true, false)
+        );
+        assert_eq!(
+            Tag::new(Namespace::Male, "sole male"),
+            Tag::from_flags("sole male", false, true)
+        );
+        assert_eq!(
+            Tag::new(Namespace::Misc, "full color"),
+            Tag::from_flags("full color", false, false)
+        );
+    }
+
+    #[test]
+    fn hitomi() {
+        for raw in &["female:sole female", "male:sole male", "full color"] {
+            assert_eq!(*raw, Tag::from_hitomi(raw).hitomi());
+        }
+
+        assert_eq!(
+            "sole female ♀",
+            Tag::from_hitomi("female:sole female").to_string()
+        );
+    }
+
+    #[test]
+    fn filter_by_namespace() -> anyhow::Result<()> {
+        let tags = ["footjob ♀", "loli ♀", "sole male ♂", "incest"]
+            .iter()
+            .map(|raw| raw.parse())
+            .collect::<anyhow::Result<Vec<Tag>>>()?;
+
+        let female = filter_namespace(&tags, Namespace::Female)
+            .map(|tag| tag.name.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["footjob", "loli"], female);
+
+        Ok(())
+    }
+}
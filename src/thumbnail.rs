@@ -0,0 +1,130 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::Cursor;
+use std::str::FromStr;
+
+use anyhow;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+
+/// Width of `bigtn` of hitomi
+pub const BIGTN_WIDTH: u32 = 236;
+
+const JPEG_QUALITY: u8 = 85;
+
+/// # Thumbnail Size
+/// Additional thumbnails uploaded as `thumbnail_{size}.jpg` next to `thumbnail.jpg`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl ThumbnailSize {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Small => "small",
+            Self::Medium => "medium",
+            Self::Large => "large",
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        match self {
+            Self::Small => 160,
+            Self::Medium => 320,
+            Self::Large => 640,
+        }
+    }
+
+    pub fn filename(&self) -> String {
+        format!("thumbnail_{}.jpg", self.as_str())
+    }
+}
+
+impl Display for ThumbnailSize {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ThumbnailSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "small" => Ok(Self::Small),
+            "medium" => Ok(Self::Medium),
+            "large" => Ok(Self::Large),
+            x => Err(anyhow::Error::msg(format!(
+                "Unknown thumbnail size `{}`, expected small, medium or large",
+                x
+            ))),
+        }
+    }
+}
+
+/// JPEG of `width` keeping the aspect ratio, from a page of any format of `image`
+///
+/// Smaller pages are not upscaled
+pub fn generate(buf: &[u8], width: u32) -> anyhow::Result<Vec<u8>> {
+    let page = image::load_from_memory(buf)?;
+
+    let thumbnail = if page.width() > width {
+        let height = (page.height() as u64 * width as u64 / page.width() as u64).max(1) as u32;
+        page.resize_exact(width, height, FilterType::Triangle)
+    } else {
+        page
+    };
+
+    let mut r = Cursor::new(vec![]);
+
+    JpegEncoder::new_with_quality(&mut r, JPEG_QUALITY).encode_image(&thumbnail.to_rgb8())?;
+
+    Ok(r.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, ThumbnailSize, BIGTN_WIDTH};
+
+    const JPG: &[u8] = include_bytes!("../tests/fixtures/images/page.jpg");
+    const WEBP: &[u8] = include_bytes!("../tests/fixtures/images/page.webp");
+
+    #[test]
+    fn generate_bigtn() -> anyhow::Result<()> {
+        for page in &[JPG, WEBP] {
+            let thumbnail = image::load_from_memory(&generate(page, BIGTN_WIDTH)?)?;
+
+            // 1280x1810
+            assert_eq!((236, 333), (thumbnail.width(), thumbnail.height()));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_without_upscale() -> anyhow::Result<()> {
+        let thumbnail = image::load_from_memory(&generate(JPG, 2000)?)?;
+
+        assert_eq!((1280, 1810), (thumbnail.width(), thumbnail.height()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_size() -> anyhow::Result<()> {
+        assert_eq!(ThumbnailSize::Large, "large".parse()?);
+        assert_eq!("thumbnail_small.jpg", ThumbnailSize::Small.filename());
+        assert!("huge".parse::<ThumbnailSize>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_corrupt_page() {
+        assert!(generate(b"<html></html>", BIGTN_WIDTH).is_err());
+    }
+}
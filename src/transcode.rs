@@ -0,0 +1,216 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::Cursor;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use anyhow;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+/// # Transcode Format
+/// Format of re-encoded images, jpeg only since the webp encoder of `image` is lossless only
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscodeFormat {
+    Jpeg,
+}
+
+impl TranscodeFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpeg",
+        }
+    }
+
+    /// Extension of `url_path`
+    pub fn ext(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+        }
+    }
+}
+
+impl Display for TranscodeFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for TranscodeFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            x => Err(anyhow::Error::msg(format!(
+                "Unknown transcode format `{}`, expected jpeg",
+                x
+            ))),
+        }
+    }
+}
+
+/// # Transcoder
+/// Re-encodes downloaded images before upload, metadata such as EXIF is not kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transcoder {
+    format: TranscodeFormat,
+    /// 1 ~ 100
+    quality: u8,
+    /// Longest edge, larger images are resized keeping the aspect ratio
+    max_edge: Option<u32>,
+}
+
+impl Transcoder {
+    pub fn new(format: TranscodeFormat) -> Self {
+        Self {
+            format,
+            quality: 85,
+            max_edge: None,
+        }
+    }
+
+    pub fn with_quality(mut self, quality: u8) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    pub fn with_max_edge(mut self, max_edge: Option<u32>) -> Self {
+        self.max_edge = max_edge;
+        self
+    }
+
+    /// (buf, ext)
+    pub fn transcode(&self, buf: &[u8]) -> anyhow::Result<(Vec<u8>, &'static str)> {
+        let image = self.resize(image::load_from_memory(buf)?);
+
+        let mut r = Cursor::new(vec![]);
+
+        match self.format {
+            TranscodeFormat::Jpeg => {
+                JpegEncoder::new_with_quality(&mut r, self.quality)
+                    .encode_image(&image.to_rgb8())?;
+            }
+        }
+
+        Ok((r.into_inner(), self.format.ext()))
+    }
+
+    fn resize(&self, image: DynamicImage) -> DynamicImage {
+        match self.max_edge {
+            Some(max_edge) if image.width().max(image.height()) > max_edge => {
+                image.resize(max_edge, max_edge, FilterType::Triangle)
+            }
+            _ => image,
+        }
+    }
+}
+
+/// # Savings
+/// Bytes of transcoded pages of a gallery
+#[derive(Debug, Default)]
+pub struct Savings {
+    pages: AtomicUsize,
+    before: AtomicU64,
+    after: AtomicU64,
+}
+
+impl Savings {
+    pub fn add(&self, before: usize, after: usize) {
+        self.pages.fetch_add(1, Ordering::Relaxed);
+        self.before.fetch_add(before as u64, Ordering::Relaxed);
+        self.after.fetch_add(after as u64, Ordering::Relaxed);
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages.load(Ordering::Relaxed)
+    }
+
+    /// Negative if transcoded pages are larger
+    pub fn saved(&self) -> i64 {
+        self.before.load(Ordering::Relaxed) as i64 - self.after.load(Ordering::Relaxed) as i64
+    }
+}
+
+/// `3 pages, 1048576 => 524288 bytes (50.0% saved)`
+impl Display for Savings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let before = self.before.load(Ordering::Relaxed);
+        let after = self.after.load(Ordering::Relaxed);
+        let ratio = if before == 0 {
+            0.0
+        } else {
+            self.saved() as f64 * 100.0 / before as f64
+        };
+
+        write!(
+            f,
+            "{} pages, {} => {} bytes ({:.1}% saved)",
+            self.pages(),
+            before,
+            after,
+            ratio
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Savings, TranscodeFormat, Transcoder};
+
+    const JPG: &[u8] = include_bytes!("../tests/fixtures/images/page.jpg");
+    const WEBP: &[u8] = include_bytes!("../tests/fixtures/images/page.webp");
+
+    #[test]
+    fn transcode_with_max_edge() -> anyhow::Result<()> {
+        let transcoder = Transcoder::new(TranscodeFormat::Jpeg).with_max_edge(Some(905));
+
+        let (buf, ext) = transcoder.transcode(JPG)?;
+        let image = image::load_from_memory(&buf)?;
+
+        assert_eq!("jpg", ext);
+        assert_eq!(image::ImageFormat::Jpeg, image::guess_format(&buf)?);
+        assert_eq!((640, 905), (image.width(), image.height()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn transcode_to_jpeg() -> anyhow::Result<()> {
+        let transcoder = Transcoder::new(TranscodeFormat::Jpeg).with_quality(50);
+
+        let (buf, ext) = transcoder.transcode(WEBP)?;
+        let image = image::load_from_memory(&buf)?;
+
+        assert_eq!("jpg", ext);
+        assert_eq!((1280, 1810), (image.width(), image.height()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn report_savings() {
+        let savings = Savings::default();
+
+        savings.add(1000, 400);
+        savings.add(1000, 600);
+
+        assert_eq!(1000, savings.saved());
+        assert_eq!(
+            "2 pages, 2000 => 1000 bytes (50.0% saved)",
+            savings.to_string()
+        );
+    }
+
+    #[test]
+    fn parse_format() -> anyhow::Result<()> {
+        assert_eq!(TranscodeFormat::Jpeg, "jpg".parse()?);
+        assert_eq!(TranscodeFormat::Jpeg, "jpeg".parse()?);
+        assert!("webp".parse::<TranscodeFormat>().is_err());
+        assert!("avif".parse::<TranscodeFormat>().is_err());
+
+        Ok(())
+    }
+}
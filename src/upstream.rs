@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::http::{self, Request, Response, Transport};
+use crate::subdomain::SubdomainResolver;
+
+/// # Upstream Endpoints
+/// Base URLs of hitomi.la, replaceable by a mock server or a caching proxy
+///
+/// ```toml
+/// [upstream]
+/// ltn = "https://ltn.hitomi.la"
+/// hitomi = "https://hitomi.la"
+/// thumbnail = "https://tn.hitomi.la"
+/// image = "https://{subdomain}.hitomi.la"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamEndpoints {
+    /// nozomi, galleries/{id}.js, galleryblock/{id}.html
+    pub ltn: String,
+    /// galleries/{id}.html, reader/{id}.html
+    pub hitomi: String,
+    /// bigtn
+    pub thumbnail: String,
+    /// `{subdomain}` is replaced by the subdomain of the image, such as `ab`
+    pub image: String,
+}
+
+impl Default for UpstreamEndpoints {
+    fn default() -> Self {
+        Self {
+            ltn: "https://ltn.hitomi.la".to_string(),
+            hitomi: "https://hitomi.la".to_string(),
+            thumbnail: "https://tn.hitomi.la".to_string(),
+            image: "https://{subdomain}.hitomi.la".to_string(),
+        }
+    }
+}
+
+impl UpstreamEndpoints {
+    pub fn ltn(&self, path: &str) -> String {
+        join(&self.ltn, path)
+    }
+
+    pub fn hitomi(&self, path: &str) -> String {
+        join(&self.hitomi, path)
+    }
+
+    pub fn thumbnail(&self, path: &str) -> String {
+        join(&self.thumbnail, path)
+    }
+
+    pub fn image(&self, subdomain: &str, path: &str) -> String {
+        join(&self.image.replace("{subdomain}", subdomain), path)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &String)> {
+        vec![
+            ("ltn", &self.ltn),
+            ("hitomi", &self.hitomi),
+            ("thumbnail", &self.thumbnail),
+            ("image", &self.image),
+        ]
+        .into_iter()
+    }
+}
+
+fn join(base: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+/// # Upstream
+/// Transport, endpoints and subdomains of images shared by parsers
+pub struct Upstream {
+    pub transport: Arc<dyn Transport>,
+    pub endpoints: UpstreamEndpoints,
+    pub subdomains: SubdomainResolver,
+}
+
+impl Upstream {
+    pub fn new(transport: Arc<dyn Transport>, endpoints: UpstreamEndpoints) -> Self {
+        Self {
+            transport,
+            endpoints,
+            subdomains: SubdomainResolver::default(),
+        }
+    }
+
+    pub fn send(&self, request: Request) -> anyhow::Result<Response> {
+        self.transport.send(request)
+    }
+}
+
+impl Default for Upstream {
+    fn default() -> Self {
+        Self::new(http::default_transport(), UpstreamEndpoints::default())
+    }
+}
+
+pub fn default_upstream() -> Arc<Upstream> {
+    Arc::new(Upstream::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UpstreamEndpoints;
+
+    #[test]
+    fn build_urls() {
+        let endpoints = UpstreamEndpoints::default();
+
+        assert_eq!(
+            "https://ltn.hitomi.la/galleries/1724122.js",
+            endpoints.ltn("galleries/1724122.js")
+        );
+        assert_eq!(
+            "https://ab.hitomi.la/images/e/0a/2fd1.jpg",
+            endpoints.image("ab", "/images/e/0a/2fd1.jpg")
+        );
+    }
+
+    #[test]
+    fn build_urls_of_mock_server() {
+        let endpoints = UpstreamEndpoints {
+            ltn: "http://127.0.0.1:8080/ltn/".to_string(),
+            hitomi: "http://127.0.0.1:8080".to_string(),
+            thumbnail: "http://127.0.0.1:8080/tn".to_string(),
+            image: "http://127.0.0.1:8080/{subdomain}".to_string(),
+        };
+
+        assert_eq!(
+            "http://127.0.0.1:8080/ltn/index-korean.nozomi",
+            endpoints.ltn("index-korean.nozomi")
+        );
+        assert_eq!(
+            "http://127.0.0.1:8080/reader/1724122.html",
+            endpoints.hitomi("/reader/1724122.html")
+        );
+        assert_eq!(
+            "http://127.0.0.1:8080/bb/images/e/0a/2fd1.jpg",
+            endpoints.image("bb", "images/e/0a/2fd1.jpg")
+        );
+    }
+}
@@ -1,10 +1,12 @@
 mod flat;
 mod get_ext;
+mod parse_date;
 mod seperate;
 mod text_store;
 
 pub use flat::flat;
 pub use get_ext::get_ext;
+pub use parse_date::parse_date;
 pub use seperate::seperate;
 pub use text_store::TextStore;
 
@@ -0,0 +1,56 @@
+use time::{OffsetDateTime, UtcOffset};
+
+/// Parses date of hitomi into UTC ISO-8601
+///
+/// `2020-09-02 10:01:00-05` => `2020-09-02T15:01:00Z`
+///
+/// Offset may be `-05`, `-0500`, `-05:00` or `Z`
+pub fn parse_date(x: &str) -> Option<String> {
+    let x = x.trim();
+
+    // offset is after `YYYY-MM-DD`
+    let (date_time, offset) = match x.get(10..)?.rfind(&['+', '-'][..]) {
+        Some(i) => x.split_at(10 + i),
+        None => (x.strip_suffix('Z')?, "+00"),
+    };
+
+    let mut offset = offset.trim().replace(':', "");
+
+    if offset.len() == 3 {
+        offset.push_str("00");
+    }
+
+    let date_time = date_time.trim().replacen('T', " ", 1);
+
+    OffsetDateTime::parse(format!("{} {}", date_time, offset), "%F %T %z")
+        .ok()
+        .map(|x| x.to_offset(UtcOffset::UTC).format("%FT%TZ"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_date;
+
+    #[test]
+    fn test_parse_date() {
+        let expected = Some("2020-09-02T15:01:00Z".to_string());
+
+        assert_eq!(expected, parse_date("2020-09-02 10:01:00-05"));
+        assert_eq!(expected, parse_date("2020-09-02 10:01:00 -05:00"));
+        assert_eq!(expected, parse_date("2020-09-02T10:01:00-0500"));
+        assert_eq!(expected, parse_date("2020-09-02 15:01:00Z"));
+        assert_eq!(
+            Some("2020-09-01T22:01:00Z".to_string()),
+            parse_date("2020-09-02 07:01:00+09")
+        );
+    }
+
+    #[test]
+    fn test_parse_date_invalid() {
+        assert_eq!(None, parse_date("2020-09-02"));
+        assert_eq!(None, parse_date("2020-09-02 10:01:00"));
+        assert_eq!(None, parse_date("September 2, 2020"));
+        assert_eq!(None, parse_date("2020-13-02 10:01:00-05"));
+        assert_eq!(None, parse_date(""));
+    }
+}
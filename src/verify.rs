@@ -0,0 +1,199 @@
+use imagesize::{self, ImageType};
+
+use crate::error::SyncError;
+use crate::utils::get_ext;
+
+const PNG_IEND: &[u8] = b"IEND\xaeB`\x82";
+
+/// # Verify Image
+/// Checks a downloaded image before upload, hitomi may respond 2xx with an empty, HTML or truncated body
+///
+/// `size` is `(width, height)` of `File`, thumbnails have no size
+///
+/// ```
+/// use madome_synchronizer::verify::verify_image;
+///
+/// assert!(verify_image("https://aa.hitomi.la/images/1/23/abc.jpg", b"<html></html>", None).is_err());
+/// ```
+pub fn verify_image(url: &str, buf: &[u8], size: Option<(u32, u32)>) -> Result<(), SyncError> {
+    check(buf, get_ext(url), size).map_err(|reason| SyncError::CorruptImage {
+        url: url.to_string(),
+        reason,
+    })
+}
+
+fn check(buf: &[u8], ext: Option<&str>, size: Option<(u32, u32)>) -> Result<(), String> {
+    if buf.is_empty() {
+        return Err("empty body".to_string());
+    }
+
+    if is_html(buf) {
+        return Err("html body".to_string());
+    }
+
+    let image_type = imagesize::image_type(buf).map_err(|_| "unknown magic bytes".to_string())?;
+
+    if let Some(ext) = ext {
+        if !is_ext_of(image_type, ext) {
+            return Err(format!("{:?} body of .{}", image_type, ext));
+        }
+    }
+
+    if !is_complete(image_type, buf) {
+        return Err(format!("truncated {:?}, {} bytes", image_type, buf.len()));
+    }
+
+    if let Some((width, height)) = size {
+        let decoded =
+            imagesize::blob_size(buf).map_err(|err| format!("can't decode size: {:?}", err))?;
+
+        if (decoded.width, decoded.height) != (width as usize, height as usize) {
+            return Err(format!(
+                "{}x{}, expected {}x{}",
+                decoded.width, decoded.height, width, height
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Error pages of CDN
+fn is_html(buf: &[u8]) -> bool {
+    let head = &buf[..buf.len().min(64)];
+    let head = String::from_utf8_lossy(head)
+        .trim_start()
+        .to_ascii_lowercase();
+
+    head.starts_with("<!doctype") || head.starts_with("<html") || head.starts_with("<?xml")
+}
+
+/// Unknown extensions accept any image
+fn is_ext_of(image_type: ImageType, ext: &str) -> bool {
+    match ext.to_ascii_lowercase().as_str() {
+        "jpg" | "jpeg" => image_type == ImageType::Jpeg,
+        "png" => image_type == ImageType::Png,
+        "gif" => image_type == ImageType::Gif,
+        "webp" => image_type == ImageType::Webp,
+        "avif" => matches!(image_type, ImageType::Avif | ImageType::Heif),
+        _ => true,
+    }
+}
+
+/// Trailer of the format is in `buf`
+fn is_complete(image_type: ImageType, buf: &[u8]) -> bool {
+    match image_type {
+        // some encoders pad after EOI
+        ImageType::Jpeg => {
+            let end = buf.iter().rposition(|x| *x != 0).map_or(0, |i| i + 1);
+            buf[..end].ends_with(&[0xff, 0xd9])
+        }
+        ImageType::Png => buf.ends_with(PNG_IEND),
+        ImageType::Gif => buf.ends_with(&[0x3b]),
+        // RIFF size excludes `RIFF` and itself
+        ImageType::Webp => {
+            matches!(read_u32_le(&buf[4..]), Some(size) if buf.len() as u64 >= size as u64 + 8)
+        }
+        ImageType::Avif | ImageType::Heif => is_complete_boxes(buf),
+        _ => true,
+    }
+}
+
+/// Every top level box of ISO-BMFF fits in `buf`
+fn is_complete_boxes(buf: &[u8]) -> bool {
+    let mut offset = 0usize;
+
+    while offset < buf.len() {
+        let size = match read_u32_be(&buf[offset..]) {
+            Some(size) => size as u64,
+            None => return false,
+        };
+
+        let size = match size {
+            // to the end of file
+            0 => return true,
+            // 64 bits size after type
+            1 => match buf.get(offset + 8..).and_then(read_u64_be) {
+                Some(size) => size,
+                None => return false,
+            },
+            size => size,
+        };
+
+        if size < 8 || offset as u64 + size > buf.len() as u64 {
+            return false;
+        }
+
+        offset += size as usize;
+    }
+
+    true
+}
+
+fn read_u32_le(buf: &[u8]) -> Option<u32> {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(buf.get(..4)?);
+    Some(u32::from_le_bytes(bytes))
+}
+
+fn read_u32_be(buf: &[u8]) -> Option<u32> {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(buf.get(..4)?);
+    Some(u32::from_be_bytes(bytes))
+}
+
+fn read_u64_be(buf: &[u8]) -> Option<u64> {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(buf.get(..8)?);
+    Some(u64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, verify_image};
+    use crate::error::ErrorKind;
+
+    const JPG: &[u8] = include_bytes!("../tests/fixtures/images/page.jpg");
+    const WEBP: &[u8] = include_bytes!("../tests/fixtures/images/page.webp");
+    const AVIF: &[u8] = include_bytes!("../tests/fixtures/images/page.avif");
+
+    const SIZE: Option<(u32, u32)> = Some((1280, 1810));
+
+    #[test]
+    fn verify_fixtures() {
+        assert_eq!(Ok(()), check(JPG, Some("jpg"), SIZE));
+        assert_eq!(Ok(()), check(WEBP, Some("webp"), SIZE));
+        assert_eq!(Ok(()), check(AVIF, Some("avif"), SIZE));
+        assert_eq!(Ok(()), check(JPG, None, None));
+    }
+
+    #[test]
+    fn reject_error_bodies() {
+        assert!(check(b"", Some("jpg"), SIZE).is_err());
+        assert!(check(b"\n<!DOCTYPE html><html>404</html>", Some("jpg"), SIZE).is_err());
+        assert!(check(b"<html><body>502 Bad Gateway</body></html>", None, None).is_err());
+    }
+
+    #[test]
+    fn reject_mismatched() {
+        // webp of .jpg
+        assert!(check(WEBP, Some("jpg"), SIZE).is_err());
+        assert!(check(JPG, Some("avif"), SIZE).is_err());
+        assert!(check(JPG, Some("jpg"), Some((1280, 1811))).is_err());
+    }
+
+    #[test]
+    fn reject_truncated() {
+        for buf in &[JPG, WEBP, AVIF] {
+            assert!(check(&buf[..buf.len() - 2], None, None).is_err());
+        }
+    }
+
+    #[test]
+    fn corrupt_image_is_retryable() {
+        let err = verify_image("https://aa.hitomi.la/webp/1/23/abc.webp", JPG, SIZE).unwrap_err();
+
+        assert_eq!(ErrorKind::CorruptImage, err.kind());
+        assert!(err.kind().is_retryable());
+    }
+}
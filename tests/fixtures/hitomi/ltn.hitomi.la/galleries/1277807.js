@@ -0,0 +1 @@
+var galleryinfo = {"language_localname":"한국어","language":"korean","date":"2018-08-28 09:12:00-05","files":[{"width":1280,"hash":"4a467fcc25902d31f4672977eb1a2d847a7d8dd400efd68956f4bcec42aaa9b2","haswebp":1,"name":"01.jpg","height":1810,"hasavif":0},{"width":1280,"hash":"222b32232a72ee387b6fb1b1e388faea3c449e87df62b4f2f8b97a7b542e6fdb","haswebp":1,"name":"02.jpg","height":1810,"hasavif":0}],"tags":[{"tag":"cosplaying","female":"1","male":""}],"japanese_title":null,"title":"Cosplay Collection","id":"1277807","type":"doujinshi","artists":[{"artist":"airandou"}],"groups":null,"parodys":null,"characters":null}
\ No newline at end of file
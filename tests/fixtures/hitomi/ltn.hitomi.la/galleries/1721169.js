@@ -0,0 +1 @@
+var galleryinfo = {"language_localname":"한국어","language":"korean","date":"2020-08-20 07:13:00-05","files":[{"width":1280,"hash":"5997bc2ec87377c49949cb1387d33f8569e8e3af4eda8ea8b98abbb6ce69b9e1","haswebp":1,"name":"01.jpg","height":1810,"hasavif":1},{"width":1280,"hash":"bf46026cf1d7a9f266b1ce5fcc7e2db23e78adebb1774614ac24932005ff19ab","haswebp":1,"name":"02.jpg","height":1810,"hasavif":0},{"width":1280,"hash":"b0ae3de2afcb46ff25a68ae6a3cb06f7654671f83564643ba16f68df39fabb50","haswebp":1,"name":"03.jpg","height":1810,"hasavif":1},{"width":1280,"hash":"b6ab61d3cd75e316cab7c207c83240fed145bcdc6a267ee1835ebecdb78d6e08","haswebp":1,"name":"04.jpg","height":1810,"hasavif":0},{"width":1280,"hash":"16c87ce9ea64d999ed469da1f815b35861e019a84333c54fd5e7f585f9d420ab","haswebp":1,"name":"05.jpg","height":1810,"hasavif":1},{"width":1280,"hash":"811fa9371d8362b7c7fd47829eef665fe31f362c2f1508df4a30098193d441e0","haswebp":1,"name":"06.jpg","height":1810,"hasavif":0},{"width":1280,"hash":"a05563f412ffa32c047ad7ef9d552c2b18286b56f9a7aaef5c1b9fb6c7945926","haswebp":1,"name":"07.jpg","height":1810,"hasavif":1},{"width":1280,"hash":"89eb4ea51821bc4b19f3d9038df457248b5424d50c33ef3690a4949cfe453fc2","haswebp":1,"name":"08.jpg","height":1810,"hasavif":0},{"width":1280,"hash":"65dc87dbf90105b3224e7b8e49090f7233145172219bc748a69b40d75b273629","haswebp":1,"name":"09.jpg","height":1810,"hasavif":1},{"width":1280,"hash":"789d0b48dc1696624cb865687a30cddb63a21cf47c07f9bb9bd7a1a4a1c18813","haswebp":1,"name":"10.jpg","height":1810,"hasavif":0}],"tags":[{"tag":"sole female","female":"1","male":"","url":"/tag/female:sole%20female-all.html"},{"tag":"sole male","female":"","male":"1","url":"/tag/male:sole%20male-all.html"},{"tag":"full color","url":"/tag/full%20color-all.html"}],"japanese_title":null,"title":"Fixture Gallery | 픽스쳐 갤러리","id":"1721169","type":"doujinshi","artists":[{"artist":"airandou","url":"/artist/airandou-all.html"}],"groups":null,"parodys":null,"characters":null}
\ No newline at end of file
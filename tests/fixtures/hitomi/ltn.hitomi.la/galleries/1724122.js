@@ -0,0 +1 @@
+var galleryinfo = {"language_localname":"한국어","language":"korean","date":"2020-09-02 10:01:00-05","files":[{"width":1280,"hash":"a3adfad50949d854246ab97cd41a34bc468a3c926878383540c3c1d754827375","haswebp":1,"name":"01.jpg","height":1810,"hasavif":0},{"width":1280,"hash":"ad2bb7eb3cad5ed1b582cf5e63060790529613864ce78bc91eb0f62454c2e200","haswebp":1,"name":"02.jpg","height":1810,"hasavif":0},{"width":1280,"hash":"f18ac2f28f9e2fb53a2b0d8a1757a1e11fa2d15594453082320000c55f37e6ce","haswebp":1,"name":"03.jpg","height":1810,"hasavif":0}],"tags":[{"tag":"footjob","female":"1","male":""},{"tag":"loli","female":"1","male":""},{"tag":"sister","female":"1","male":""},{"tag":"incest"}],"japanese_title":null,"title":"Tsundere Imouto | 츤데레 여동생","id":"1724122","type":"manga","artists":[{"artist":"airandou"}],"groups":null,"parodys":null,"characters":null}
\ No newline at end of file
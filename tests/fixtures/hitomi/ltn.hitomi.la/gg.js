@@ -0,0 +1,14 @@
+"use strict";
+gg = {
+m: function(g) {
+var o = 0;
+switch (g) {
+case 1234:
+case 2345:
+o = 1; break;
+}
+return o;
+},
+s: function(h) { var m = /(..)(.)$/.exec(h); return parseInt(m[2]+m[1], 16).toString(10); },
+b: '1650348721/'
+};
@@ -0,0 +1,388 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use madome_client::auth::Token;
+use madome_synchronizer::config::{Config, RetryConfig, RetryPolicy};
+use madome_synchronizer::http::{self, FixtureTransport, Request, Transport};
+use madome_synchronizer::sync::Context;
+use madome_synchronizer::upstream::{Upstream, UpstreamEndpoints};
+use madome_synchronizer::utils::TextStore;
+
+pub const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/hitomi");
+
+/// Body of every jpg image and thumbnail of the mock hitomi, 1280x1810 same as fixtures
+pub const IMAGE: &[u8] = include_bytes!("../fixtures/images/page.jpg");
+
+pub const IMAGE_WEBP: &[u8] = include_bytes!("../fixtures/images/page.webp");
+
+pub const IMAGE_AVIF: &[u8] = include_bytes!("../fixtures/images/page.avif");
+
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Recorded {
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+}
+
+/// Responds `status` and `body` to the requests whose path contains `pattern`, `times` times
+struct Failure {
+    method: Option<String>,
+    pattern: String,
+    status: u16,
+    body: Vec<u8>,
+    times: Option<usize>,
+}
+
+type Handler = dyn Fn(&Recorded) -> (u16, Vec<u8>) + Send + Sync;
+
+/// # Mock Server
+/// In-process http server which records every requests
+pub struct MockServer {
+    server: Arc<tiny_http::Server>,
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<Recorded>>>,
+    failures: Arc<Mutex<Vec<Failure>>>,
+}
+
+impl MockServer {
+    fn start(handler: Box<Handler>) -> Self {
+        let server = Arc::new(tiny_http::Server::http("127.0.0.1:0").unwrap());
+        let base_url = format!("http://{}", server.server_addr());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let failures: Arc<Mutex<Vec<Failure>>> = Arc::new(Mutex::new(vec![]));
+
+        {
+            let server = Arc::clone(&server);
+            let requests = Arc::clone(&requests);
+            let failures = Arc::clone(&failures);
+
+            thread::spawn(move || {
+                for mut request in server.incoming_requests() {
+                    let mut body = vec![];
+                    request.as_reader().read_to_end(&mut body).unwrap();
+
+                    let recorded = Recorded {
+                        method: request.method().to_string(),
+                        path: request.url().to_string(),
+                        headers: request
+                            .headers()
+                            .iter()
+                            .map(|h| (h.field.to_string(), h.value.to_string()))
+                            .collect(),
+                        body,
+                    };
+
+                    let injected = failures.lock().unwrap().iter_mut().find_map(|failure| {
+                        let method_matches = match failure.method {
+                            Some(ref method) => *method == recorded.method,
+                            None => true,
+                        };
+
+                        if !method_matches
+                            || !recorded.path.contains(&failure.pattern)
+                            || failure.times == Some(0)
+                        {
+                            return None;
+                        }
+
+                        if let Some(ref mut times) = failure.times {
+                            *times -= 1;
+                        }
+
+                        Some((failure.status, failure.body.clone()))
+                    });
+
+                    let (status, body) = match injected {
+                        Some(injected) => injected,
+                        None => handler(&recorded),
+                    };
+
+                    requests.lock().unwrap().push(recorded);
+
+                    let response = tiny_http::Response::from_data(body).with_status_code(status);
+                    let _ = request.respond(response);
+                }
+            });
+        }
+
+        Self {
+            server,
+            base_url,
+            requests,
+            failures,
+        }
+    }
+
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Every requests whose path contains `pattern` fails by `status`
+    pub fn fail(&self, pattern: &str, status: u16) {
+        self.inject(None, pattern, status, vec![], None);
+    }
+
+    /// The first `times` requests whose path contains `pattern` fail by `status`
+    pub fn fail_times(&self, pattern: &str, status: u16, times: usize) {
+        self.inject(None, pattern, status, vec![], Some(times));
+    }
+
+    pub fn fail_method(&self, method: &str, pattern: &str, status: u16) {
+        self.inject(Some(method.to_string()), pattern, status, vec![], None);
+    }
+
+    /// Every requests whose path contains `pattern` are responded by `body`
+    pub fn respond(&self, pattern: &str, body: impl Into<Vec<u8>>) {
+        self.inject(None, pattern, 200, body.into(), None);
+    }
+
+    /// The first `times` requests whose path contains `pattern` are responded by `body`
+    pub fn respond_times(&self, pattern: &str, body: impl Into<Vec<u8>>, times: usize) {
+        self.inject(None, pattern, 200, body.into(), Some(times));
+    }
+
+    pub fn clear_failures(&self) {
+        self.failures.lock().unwrap().clear();
+    }
+
+    fn inject(
+        &self,
+        method: Option<String>,
+        pattern: &str,
+        status: u16,
+        body: Vec<u8>,
+        times: Option<usize>,
+    ) {
+        self.failures.lock().unwrap().push(Failure {
+            method,
+            pattern: pattern.to_string(),
+            status,
+            body,
+            times,
+        });
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+    }
+}
+
+/// # Mock Hitomi
+/// Serves `tests/fixtures/hitomi` under `/{host}/...`,
+/// and `IMAGE` of the format for every images and thumbnails
+pub struct MockHitomi {
+    pub server: MockServer,
+}
+
+impl MockHitomi {
+    pub fn start() -> Self {
+        let fixture = FixtureTransport::replay(FIXTURE_DIR);
+
+        let server = MockServer::start(Box::new(move |recorded| {
+            if recorded.path.contains("/webp/") {
+                return (200, IMAGE_WEBP.to_vec());
+            }
+
+            if recorded.path.contains("/avif/") {
+                return (200, IMAGE_AVIF.to_vec());
+            }
+
+            if ["/images/", "/bigtn/"]
+                .iter()
+                .any(|x| recorded.path.contains(x))
+            {
+                return (200, IMAGE.to_vec());
+            }
+
+            // /ltn.hitomi.la/galleries/1724122.js => http://ltn.hitomi.la/galleries/1724122.js
+            let mut request = Request::get(format!("http:/{}", recorded.path));
+
+            if let Some(range) = recorded.header("Range") {
+                request = request.header("Range", range);
+            }
+
+            match fixture.send(request) {
+                Ok(response) => (response.status.as_u16(), response.body.to_vec()),
+                Err(err) => (500, err.to_string().into_bytes()),
+            }
+        }));
+
+        Self { server }
+    }
+
+    pub fn endpoints(&self) -> UpstreamEndpoints {
+        let base_url = &self.server.base_url;
+
+        UpstreamEndpoints {
+            ltn: format!("{}/ltn.hitomi.la", base_url),
+            hitomi: format!("{}/hitomi.la", base_url),
+            thumbnail: format!("{}/tn.hitomi.la", base_url),
+            image: format!("{}/{{subdomain}}.hitomi.la", base_url),
+        }
+    }
+}
+
+/// # Mock Madome
+/// Auth, book and file endpoints of Madome
+///
+/// Every `GET` is `404 Not Found` so every galleries are synchronized,
+/// and the others are `200 OK`
+pub struct MockMadome {
+    pub server: MockServer,
+}
+
+impl MockMadome {
+    pub fn start() -> Self {
+        let server = MockServer::start(Box::new(|recorded| match recorded.method.as_str() {
+            "GET" => (404, vec![]),
+            _ => (200, b"{}".to_vec()),
+        }));
+
+        Self { server }
+    }
+
+    /// Uploaded files of a gallery, `(url_path, body)`
+    pub fn uploads(&self, id: u32) -> Vec<(String, Vec<u8>)> {
+        let prefix = format!("image/library/{}/", id);
+
+        let mut r = self
+            .server
+            .requests()
+            .into_iter()
+            .filter(|recorded| recorded.method != "GET")
+            .filter_map(|recorded| {
+                let i = recorded.path.find(&prefix)?;
+                Some((recorded.path[i..].to_string(), recorded.body))
+            })
+            .collect::<Vec<_>>();
+
+        r.sort();
+        r
+    }
+
+    /// Requests creating books
+    pub fn created_books(&self) -> Vec<Recorded> {
+        self.server
+            .requests()
+            .into_iter()
+            .filter(|recorded| recorded.method != "GET")
+            .filter(|recorded| !recorded.path.contains("image/library/"))
+            .filter(|recorded| !recorded.path.contains("auth"))
+            .collect()
+    }
+}
+
+/// # Harness
+/// Mock servers, and config of a temporary directory
+pub struct Harness {
+    pub hitomi: MockHitomi,
+    pub madome: MockMadome,
+    pub dir: PathBuf,
+    pub config: Config,
+}
+
+static SEQ: AtomicUsize = AtomicUsize::new(0);
+
+impl Harness {
+    pub fn new() -> Self {
+        let hitomi = MockHitomi::start();
+        let madome = MockMadome::start();
+
+        let dir = std::env::temp_dir().join(format!(
+            "madome-synchronizer-{}-{}",
+            std::process::id(),
+            SEQ.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+
+        let mut config = Config {
+            madome_url: madome.server.base_url.clone(),
+            file_repository_url: madome.server.base_url.clone(),
+            token_path: path(".token"),
+            fail_store_path: path("fail_store.txt"),
+            cursor_path: path("cursor.json"),
+            progress_dir: path("progress"),
+            hash_index_path: path("hash_index.txt"),
+            journal_path: path("journal.jsonl"),
+            report_dir: path("reports"),
+            startup_delay: 0,
+            upstream: hitomi.endpoints(),
+            ..Config::default()
+        };
+        config.concurrency.images = 4;
+        // tests opt in retries of stages
+        config.retry = {
+            let policy = RetryPolicy {
+                max_attempts: 1,
+                backoff: 10,
+                jitter: 0.0,
+                ..RetryPolicy::default()
+            };
+
+            RetryConfig {
+                parse_book: policy.clone(),
+                parse_images: policy.clone(),
+                add_thumbnail: policy.clone(),
+                add_images: policy.clone(),
+                add_image_list: policy.clone(),
+                add_book: policy,
+            }
+        };
+
+        fs::write(&config.fail_store_path, "").unwrap();
+
+        Self {
+            hitomi,
+            madome,
+            dir,
+            config,
+        }
+    }
+
+    pub fn context(&self) -> Context {
+        let transport: Arc<dyn Transport> = http::default_transport();
+        let upstream = Upstream::new(transport, self.config.upstream.clone());
+        let token = Token {
+            token: "token".to_string(),
+        };
+
+        Context::new(self.config.clone(), Arc::new(upstream), token).unwrap()
+    }
+
+    /// fail_store file of the last synchronize
+    pub fn failed_ids(&self) -> Vec<u32> {
+        let fail_store = TextStore::<u32>::from_file(&self.config.fail_store_path).unwrap();
+        let mut r = fail_store.iter().copied().collect::<Vec<_>>();
+        r.sort_unstable();
+        r
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
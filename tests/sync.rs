@@ -0,0 +1,918 @@
+mod support;
+
+use std::fs;
+
+use madome_synchronizer::error::{self, ErrorKind};
+use madome_synchronizer::journal::Journal;
+use madome_synchronizer::metrics;
+use madome_synchronizer::parser::ImageFormat;
+use madome_synchronizer::report::Report;
+use madome_synchronizer::stage::Stage;
+use madome_synchronizer::sync::{resume, retry_fail, sync, sync_id, synchronize_ids};
+use madome_synchronizer::thumbnail::ThumbnailSize;
+use madome_synchronizer::transcode::TranscodeFormat;
+
+use support::{Harness, IMAGE};
+
+const ID: u32 = 1724122;
+
+const GALLERYINFO_WITHOUT_TITLE: &str = r#"var galleryinfo = {"title":"","files":[{"width":1280,"height":1810,"hash":"a3adfad50949d854246ab97cd41a34bc468a3c926878383540c3c1d754827375","haswebp":1,"name":"01.jpg"}]}"#;
+
+#[test]
+fn sync_images_and_book() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    sync_id(&context, vec![ID])?;
+
+    let uploads = harness.madome.uploads(ID);
+    let paths = uploads
+        .iter()
+        .map(|(path, _)| path.as_str())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        vec![
+            "image/library/1724122/1.jpg",
+            "image/library/1724122/2.jpg",
+            "image/library/1724122/3.jpg",
+            "image/library/1724122/image_list.txt",
+            "image/library/1724122/thumbnail.jpg",
+        ],
+        paths
+    );
+    assert!(uploads
+        .iter()
+        .filter(|(path, _)| !path.ends_with(".txt"))
+        .all(|(_, body)| body.as_slice() == IMAGE));
+
+    let (_, image_list) = &uploads[3];
+    let image_list = String::from_utf8(image_list.clone())?;
+    let base_url = &harness.madome.server.base_url;
+
+    assert_eq!(
+        (1..=3)
+            .map(|page| format!("{}/image/library/{}/{}.jpg", base_url, ID, page))
+            .collect::<Vec<_>>(),
+        image_list.lines().collect::<Vec<_>>()
+    );
+
+    let books = harness.madome.created_books();
+
+    assert_eq!(1, books.len());
+    assert!(books[0].text().contains(&ID.to_string()));
+
+    assert!(harness.failed_ids().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn send_referer_to_hitomi() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    sync(&context, ID, true, false)?;
+
+    let image_requests = harness
+        .hitomi
+        .server
+        .requests()
+        .into_iter()
+        .filter(|recorded| recorded.path.contains("/images/"))
+        .collect::<Vec<_>>();
+
+    assert_eq!(3, image_requests.len());
+
+    for recorded in image_requests {
+        assert_eq!(
+            Some(
+                format!(
+                    "{}/hitomi.la/reader/{}.html",
+                    harness.hitomi.server.base_url, ID
+                )
+                .as_str()
+            ),
+            recorded.header("Referer")
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn fail_add_images() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    harness.hitomi.server.fail_times("/images/", 500, 1);
+
+    sync_id(&context, vec![ID])?;
+
+    let uploads = harness.madome.uploads(ID);
+
+    // image_list.txt is not uploaded without every images
+    assert!(uploads
+        .iter()
+        .any(|(path, _)| path.ends_with("thumbnail.jpg")));
+    assert!(!uploads
+        .iter()
+        .any(|(path, _)| path.ends_with("image_list.txt")));
+    // book is synchronized separately
+    assert_eq!(1, harness.madome.created_books().len());
+
+    assert_eq!(vec![ID], harness.failed_ids());
+    // the failed page is not counted
+    assert!(!Journal::read(&harness.config.journal_path)?.is_fulfilled(ID, &Stage::AddImages));
+
+    Ok(())
+}
+
+#[test]
+fn fail_parse_images() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    harness.hitomi.server.fail("/galleries/1724122.js", 503);
+
+    sync_id(&context, vec![ID])?;
+
+    assert!(harness.madome.uploads(ID).is_empty());
+    assert!(harness.madome.created_books().is_empty());
+    assert_eq!(vec![ID], harness.failed_ids());
+
+    Ok(())
+}
+
+#[test]
+fn fail_add_book() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    harness.madome.server.fail_method("POST", "book", 500);
+    harness.madome.server.fail_method("PUT", "book", 500);
+
+    synchronize_ids(&context, vec![ID])?;
+
+    assert!(context.fail_store.lock().unwrap().has(&ID));
+
+    Ok(())
+}
+
+#[test]
+fn retry_failed_ids() -> anyhow::Result<()> {
+    let harness = Harness::new();
+
+    {
+        let context = harness.context();
+        harness.hitomi.server.fail("/bigtn/", 500);
+
+        sync_id(&context, vec![ID])?;
+
+        assert_eq!(vec![ID], harness.failed_ids());
+    }
+
+    harness.hitomi.server.clear_failures();
+
+    let context = harness.context();
+
+    retry_fail(&context)?;
+
+    assert!(harness
+        .madome
+        .uploads(ID)
+        .iter()
+        .any(|(path, _)| path.ends_with("image_list.txt")));
+    assert!(harness.failed_ids().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn retry_stage_by_config() -> anyhow::Result<()> {
+    let mut harness = Harness::new();
+    harness.config.retry.add_images.max_attempts = 2;
+
+    let context = harness.context();
+
+    harness.hitomi.server.fail_times("/images/", 500, 1);
+
+    sync_id(&context, vec![ID])?;
+
+    assert!(harness.failed_ids().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn retry_corrupt_image() -> anyhow::Result<()> {
+    let mut harness = Harness::new();
+    harness.config.retry.add_images.max_attempts = 2;
+
+    let context = harness.context();
+
+    harness.hitomi.server.respond_times(
+        "/images/",
+        "<!DOCTYPE html><html>502 Bad Gateway</html>",
+        1,
+    );
+
+    sync(&context, ID, true, false)?;
+
+    assert!(harness
+        .madome
+        .uploads(ID)
+        .iter()
+        .filter(|(path, _)| path.ends_with(".jpg"))
+        .all(|(_, body)| body.as_slice() == IMAGE));
+
+    Ok(())
+}
+
+#[test]
+fn skip_upload_of_truncated_image() -> anyhow::Result<()> {
+    let mut harness = Harness::new();
+    harness.config.retry.add_images.max_attempts = 2;
+
+    let context = harness.context();
+
+    harness
+        .hitomi
+        .server
+        .respond("/images/", &IMAGE[..IMAGE.len() - 2]);
+
+    let err = sync(&context, ID, true, false).unwrap_err();
+
+    assert_eq!(ErrorKind::CorruptImage, error::kind(&err));
+    assert!(!harness
+        .madome
+        .uploads(ID)
+        .iter()
+        .any(|(path, _)| path.ends_with("/1.jpg")
+            || path.ends_with("/2.jpg")
+            || path.ends_with("/3.jpg")));
+
+    Ok(())
+}
+
+#[test]
+fn skip_retry_of_not_found() -> anyhow::Result<()> {
+    let mut harness = Harness::new();
+    harness.config.retry.parse_images.max_attempts = 3;
+
+    let context = harness.context();
+
+    harness.hitomi.server.fail("/galleries/1724122.js", 404);
+
+    let err = sync(&context, ID, true, false).unwrap_err();
+
+    assert_eq!(ErrorKind::UpstreamNotFound, error::kind(&err));
+    assert_eq!(
+        1,
+        harness
+            .hitomi
+            .server
+            .requests()
+            .iter()
+            .filter(|recorded| recorded.path.ends_with("/galleries/1724122.js"))
+            .count()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn parse_book_from_galleries_js() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    sync(&context, ID, false, true)?;
+
+    assert_eq!(1, harness.madome.created_books().len());
+    assert!(!harness
+        .hitomi
+        .server
+        .requests()
+        .iter()
+        .any(|recorded| recorded.path.contains("/galleryblock/")));
+
+    Ok(())
+}
+
+#[test]
+fn fallback_to_html() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    harness
+        .hitomi
+        .server
+        .respond("/galleries/1724122.js", GALLERYINFO_WITHOUT_TITLE);
+
+    sync(&context, ID, false, true)?;
+
+    assert_eq!(1, harness.madome.created_books().len());
+    assert!(harness
+        .hitomi
+        .server
+        .requests()
+        .iter()
+        .any(|recorded| recorded.path.contains("/galleryblock/1724122.html")));
+    assert_eq!(
+        vec!["Title (galleryinfo.title): 1 galleries, 1724122"],
+        context.layout_drift.lock().unwrap().summary()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn summarize_layout_drift() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    harness
+        .hitomi
+        .server
+        .respond("/galleries/1724122.js", GALLERYINFO_WITHOUT_TITLE);
+    harness
+        .hitomi
+        .server
+        .respond("/galleryblock/1724122.html", "<div>redesigned</div>");
+
+    synchronize_ids(&context, vec![ID])?;
+
+    assert_eq!(
+        vec![
+            "Title (galleryinfo.title): 1 galleries, 1724122",
+            "Title (h1.lillie > a): 1 galleries, 1724122",
+        ],
+        context.layout_drift.lock().unwrap().summary()
+    );
+    assert!(context.fail_store.lock().unwrap().has(&ID));
+
+    Ok(())
+}
+
+#[test]
+fn refresh_gg_js_on_not_found() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    harness
+        .hitomi
+        .server
+        .respond_times("/gg.js", "var o = 1; b: '1000000000/'", 1);
+    harness.hitomi.server.fail("/images/1000000000/", 404);
+
+    sync(&context, ID, true, false)?;
+
+    assert!(harness
+        .madome
+        .uploads(ID)
+        .iter()
+        .any(|(path, _)| path.ends_with("image_list.txt")));
+    assert_eq!(
+        2,
+        harness
+            .hitomi
+            .server
+            .requests()
+            .iter()
+            .filter(|recorded| recorded.path.ends_with("/gg.js"))
+            .count()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn keep_gg_js_on_rejected() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    harness.hitomi.server.fail("/images/", 403);
+
+    sync_id(&context, vec![ID])?;
+
+    assert_eq!(vec![ID], harness.failed_ids());
+    assert_eq!(
+        1,
+        harness
+            .hitomi
+            .server
+            .requests()
+            .iter()
+            .filter(|recorded| recorded.path.ends_with("/gg.js"))
+            .count()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn download_preferred_format() -> anyhow::Result<()> {
+    let mut harness = Harness::new();
+    harness.config.images.formats = vec![ImageFormat::Avif, ImageFormat::Webp];
+
+    let context = harness.context();
+
+    // every file has webp, but not avif
+    harness.hitomi.server.fail_times("/webp/", 404, 1);
+
+    sync(&context, ID, true, false)?;
+
+    let paths = harness
+        .madome
+        .uploads(ID)
+        .into_iter()
+        .map(|(path, _)| path)
+        .filter(|path| !path.ends_with("thumbnail.jpg") && !path.ends_with(".txt"))
+        .collect::<Vec<_>>();
+
+    assert_eq!(3, paths.len());
+    assert_eq!(
+        1,
+        paths.iter().filter(|path| path.ends_with(".jpg")).count()
+    );
+    assert_eq!(
+        2,
+        paths.iter().filter(|path| path.ends_with(".webp")).count()
+    );
+    assert!(!harness
+        .hitomi
+        .server
+        .requests()
+        .iter()
+        .any(|recorded| recorded.path.contains("/avif/")));
+
+    Ok(())
+}
+
+#[test]
+fn fail_preferred_format_on_rejected() -> anyhow::Result<()> {
+    let mut harness = Harness::new();
+    harness.config.images.formats = vec![ImageFormat::Webp];
+
+    let context = harness.context();
+
+    harness.hitomi.server.fail("/webp/", 403);
+
+    sync_id(&context, vec![ID])?;
+
+    assert_eq!(vec![ID], harness.failed_ids());
+    assert!(!harness
+        .madome
+        .uploads(ID)
+        .iter()
+        .any(|(path, _)| path.ends_with(".jpg") && !path.ends_with("thumbnail.jpg")));
+
+    Ok(())
+}
+
+#[test]
+fn resume_missing_pages() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    harness.hitomi.server.fail_times("/images/", 500, 1);
+
+    sync(&context, ID, true, false).unwrap_err();
+
+    let image_requests = || {
+        harness
+            .hitomi
+            .server
+            .requests()
+            .iter()
+            .filter(|recorded| {
+                recorded.path.contains("/images/") || recorded.path.contains("/bigtn/")
+            })
+            .count()
+    };
+
+    // thumbnail and 3 pages
+    assert_eq!(4, image_requests());
+    assert!(harness
+        .dir
+        .join("progress")
+        .join(format!("{}.json", ID))
+        .exists());
+
+    sync(&context, ID, true, false)?;
+
+    // the failed page only
+    assert_eq!(5, image_requests());
+
+    let uploads = harness.madome.uploads(ID);
+    let (_, image_list) = uploads
+        .iter()
+        .find(|(path, _)| path.ends_with("image_list.txt"))
+        .unwrap();
+
+    assert_eq!(3, String::from_utf8(image_list.clone())?.lines().count());
+    assert!(!harness
+        .dir
+        .join("progress")
+        .join(format!("{}.json", ID))
+        .exists());
+
+    Ok(())
+}
+
+#[test]
+fn reference_uploaded_image_of_same_hash() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    sync(&context, ID, true, false)?;
+
+    // the first page of 1724122 is shared
+    harness.hitomi.server.respond(
+        "/galleries/1277807.js",
+        r#"var galleryinfo = {"title":"Shared","files":[{"width":1280,"height":1810,"hash":"a3adfad50949d854246ab97cd41a34bc468a3c926878383540c3c1d754827375","haswebp":1,"name":"01.jpg"},{"width":1280,"height":1810,"hash":"222b32232a72ee387b6fb1b1e388faea3c449e87df62b4f2f8b97a7b542e6fdb","haswebp":1,"name":"02.jpg"}]}"#,
+    );
+
+    sync(&context, 1277807, true, false)?;
+
+    assert_eq!(
+        1,
+        harness
+            .hitomi
+            .server
+            .requests()
+            .iter()
+            .filter(|recorded| recorded.path.contains("/images/")
+                && recorded.path.ends_with(
+                    "a3adfad50949d854246ab97cd41a34bc468a3c926878383540c3c1d754827375.jpg"
+                ))
+            .count()
+    );
+
+    let uploads = harness.madome.uploads(1277807);
+    let paths = uploads
+        .iter()
+        .map(|(path, _)| path.as_str())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        vec![
+            "image/library/1277807/2.jpg",
+            "image/library/1277807/image_list.txt",
+            "image/library/1277807/thumbnail.jpg",
+        ],
+        paths
+    );
+
+    let (_, image_list) = &uploads[1];
+    let base_url = &harness.madome.server.base_url;
+
+    assert_eq!(
+        vec![
+            format!("{}/image/library/{}/1.jpg", base_url, ID),
+            format!("{}/image/library/1277807/2.jpg", base_url),
+        ],
+        String::from_utf8(image_list.clone())?
+            .lines()
+            .collect::<Vec<_>>()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn generate_thumbnail_of_missing_bigtn() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    harness.hitomi.server.fail("/bigtn/", 404);
+
+    sync(&context, ID, true, false)?;
+
+    let uploads = harness.madome.uploads(ID);
+    let (_, thumbnail) = uploads
+        .iter()
+        .find(|(path, _)| path.ends_with("/thumbnail.jpg"))
+        .unwrap();
+    let thumbnail = image::load_from_memory(thumbnail)?;
+
+    assert_eq!((236, 333), (thumbnail.width(), thumbnail.height()));
+
+    Ok(())
+}
+
+#[test]
+fn generate_thumbnail_sizes() -> anyhow::Result<()> {
+    let mut harness = Harness::new();
+    harness.config.images.thumbnail_sizes = vec![ThumbnailSize::Small, ThumbnailSize::Large];
+
+    let context = harness.context();
+
+    sync(&context, ID, true, false)?;
+
+    let uploads = harness.madome.uploads(ID);
+    let thumbnail = |filename: &str| {
+        uploads
+            .iter()
+            .find(|(path, _)| path.ends_with(filename))
+            .map(|(_, body)| body.clone())
+            .unwrap()
+    };
+
+    assert_eq!(IMAGE, thumbnail("/thumbnail.jpg").as_slice());
+    assert_eq!(
+        160,
+        image::load_from_memory(&thumbnail("/thumbnail_small.jpg"))?.width()
+    );
+    assert_eq!(
+        640,
+        image::load_from_memory(&thumbnail("/thumbnail_large.jpg"))?.width()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn transcode_images() -> anyhow::Result<()> {
+    let mut harness = Harness::new();
+    harness.config.transcode.format = Some(TranscodeFormat::Jpeg);
+    harness.config.transcode.max_edge = Some(905);
+
+    let context = harness.context();
+
+    sync(&context, ID, true, false)?;
+
+    let uploads = harness.madome.uploads(ID);
+    let pages = uploads
+        .iter()
+        .filter(|(path, _)| !path.contains("thumbnail") && !path.ends_with(".txt"))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        vec![
+            "image/library/1724122/1.jpg",
+            "image/library/1724122/2.jpg",
+            "image/library/1724122/3.jpg",
+        ],
+        pages
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .collect::<Vec<_>>()
+    );
+
+    for (_, body) in pages {
+        let page = image::load_from_memory(body)?;
+
+        assert_eq!((640, 905), (page.width(), page.height()));
+    }
+
+    let (_, image_list) = uploads
+        .iter()
+        .find(|(path, _)| path.ends_with("image_list.txt"))
+        .unwrap();
+
+    assert!(String::from_utf8(image_list.clone())?
+        .lines()
+        .all(|line| line.ends_with(".jpg")));
+
+    Ok(())
+}
+
+#[test]
+fn keep_smaller_originals() -> anyhow::Result<()> {
+    let mut harness = Harness::new();
+    harness.config.images.formats = vec![ImageFormat::Webp];
+    harness.config.transcode.format = Some(TranscodeFormat::Jpeg);
+
+    let context = harness.context();
+
+    // every file has webp but one
+    harness.hitomi.server.fail_times("/webp/", 404, 1);
+
+    sync(&context, ID, true, false)?;
+
+    let pages = harness
+        .madome
+        .uploads(ID)
+        .into_iter()
+        .filter(|(path, _)| !path.contains("thumbnail") && !path.ends_with(".txt"))
+        .collect::<Vec<_>>();
+
+    assert_eq!(3, pages.len());
+    assert_eq!(
+        2,
+        pages
+            .iter()
+            .filter(|(path, body)| path.ends_with(".webp")
+                && image::guess_format(body).ok() == Some(image::ImageFormat::WebP))
+            .count()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn journal_incomplete_ids() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    harness.madome.server.fail_method("POST", "book", 500);
+    harness.madome.server.fail_method("PUT", "book", 500);
+
+    sync_id(&context, vec![ID])?;
+
+    let journal = Journal::read(&harness.config.journal_path)?;
+
+    // failed ids are retried by fail_store, not resumed
+    assert!(journal.incomplete().is_empty());
+    assert!(journal.is_fulfilled(ID, &Stage::AddImageList));
+    assert!(!journal.is_fulfilled(ID, &Stage::AddBook));
+
+    harness.madome.server.clear_failures();
+
+    sync_id(&context, vec![ID])?;
+
+    assert!(Journal::read(&harness.config.journal_path)?
+        .incomplete()
+        .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn refresh_token_of_file() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    fs::write(&harness.config.token_path, "expired\n")?;
+
+    context.refresh_token()?;
+
+    assert_ne!("expired", context.token());
+    assert_eq!(
+        fs::read_to_string(&harness.config.token_path)?,
+        context.token()
+    );
+
+    harness.madome.server.fail("auth", 401);
+
+    assert_eq!(
+        ErrorKind::MadomeUnauthorized,
+        error::kind(&context.refresh_token().unwrap_err())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn resume_from_journal() -> anyhow::Result<()> {
+    let harness = Harness::new();
+
+    // crashed before adding book
+    fs::write(
+        &harness.config.journal_path,
+        format!(
+            "{{\"event\":\"started\",\"id\":{0}}}\n{{\"event\":\"fulfilled\",\"id\":{0},\"stage\":\"add_image_list\"}}\n",
+            ID
+        ),
+    )?;
+
+    let context = harness.context();
+
+    resume(&context)?;
+
+    assert!(harness.madome.uploads(ID).is_empty());
+    assert_eq!(1, harness.madome.created_books().len());
+    assert!(context.journal.incomplete().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn serve_metrics() -> anyhow::Result<()> {
+    let harness = Harness::new();
+    let context = harness.context();
+
+    sync_id(&context, vec![ID])?;
+
+    let addr = metrics::serve("127.0.0.1:0", context.metrics.clone())?;
+    let text = reqwest::blocking::get(&format!("http://{}/metrics", addr))?.text()?;
+
+    assert!(text
+        .contains(r#"madome_synchronizer_stages_total{outcome="fulfilled",stage="add_images"} 3"#));
+    assert!(text
+        .contains(r#"madome_synchronizer_stages_total{outcome="fulfilled",stage="add_book"} 1"#));
+    // 3 pages, thumbnail and image_list.txt
+    assert!(text.contains("madome_synchronizer_uploaded_bytes_count 5"));
+    assert!(text.contains("madome_synchronizer_downloaded_bytes_count 4"));
+    assert!(text.contains("madome_synchronizer_in_flight_ids 0"));
+
+    let status = reqwest::blocking::get(&format!("http://{}/", addr))?.status();
+
+    assert_eq!(404, status.as_u16());
+
+    Ok(())
+}
+
+#[test]
+fn report_stages_and_bytes() -> anyhow::Result<()> {
+    let mut harness = Harness::new();
+    harness.config.retry.add_images.max_attempts = 2;
+
+    let context = harness.context();
+
+    harness.hitomi.server.respond_times(
+        "/images/",
+        "<!DOCTYPE html><html>502 Bad Gateway</html>",
+        1,
+    );
+
+    sync_id(&context, vec![ID])?;
+
+    let report = Report::load(&context.config.report_dir, ID)?.unwrap();
+    let images = report.images.unwrap();
+    let add_images = &images.stages["add_images"];
+
+    assert!(images.is_ok());
+    assert_eq!(Some(3), images.page_count);
+    assert_eq!(
+        (3, 1, 0),
+        (add_images.calls, add_images.retries, add_images.errors)
+    );
+    assert_eq!(1, images.stages["add_image_list"].calls);
+    assert!(images.downloaded_bytes >= 3 * IMAGE.len() as u64);
+    assert!(images.uploaded_bytes >= 3 * IMAGE.len() as u64);
+
+    let book = report.book.unwrap();
+
+    assert!(book.is_ok());
+    assert_eq!(0, book.uploaded_bytes);
+    assert!(book.stages.contains_key("parse_book"));
+    assert!(book.stages.contains_key("add_book"));
+
+    // the last run is kept
+    harness.hitomi.server.fail("/galleries/", 404);
+
+    assert!(sync(&context, ID, false, true).is_err());
+
+    let report = Report::load(&context.config.report_dir, ID)?.unwrap();
+    let book = report.book.unwrap();
+
+    assert!(report.images.unwrap().is_ok());
+    assert_eq!(Some("upstream_not_found"), book.error_kind.as_deref());
+    assert_eq!(1, book.stages["parse_book"].errors);
+
+    Ok(())
+}
+
+#[test]
+fn retry_by_retryable_kinds() -> anyhow::Result<()> {
+    let mut harness = Harness::new();
+    harness.config.retry.parse_images.max_attempts = 3;
+
+    let context = harness.context();
+
+    harness.hitomi.server.fail_times("/galleries/", 404, 1);
+
+    // not found isn't retried by default
+    assert_eq!(
+        ErrorKind::UpstreamNotFound,
+        error::kind(&sync(&context, ID, true, false).unwrap_err())
+    );
+    assert_eq!(
+        1,
+        harness
+            .hitomi
+            .server
+            .requests()
+            .iter()
+            .filter(|recorded| recorded.path.ends_with("/galleries/1724122.js"))
+            .count()
+    );
+
+    let mut harness = Harness::new();
+    harness.config.retry.parse_images.max_attempts = 3;
+    harness.config.retry.parse_images.backoff = 10;
+    harness.config.retry.parse_images.retryable = Some(vec![ErrorKind::UpstreamNotFound]);
+
+    let context = harness.context();
+
+    harness.hitomi.server.fail_times("/galleries/", 404, 2);
+
+    sync(&context, ID, true, false)?;
+
+    let report = Report::load(&context.config.report_dir, ID)?.unwrap();
+    let parse_images = &report.images.unwrap().stages["parse_images"];
+
+    assert_eq!(
+        (1, 2, 0),
+        (
+            parse_images.calls,
+            parse_images.retries,
+            parse_images.errors
+        )
+    );
+    assert!(parse_images.duration_ms >= 30);
+
+    Ok(())
+}